@@ -1,11 +1,14 @@
 use std::cell::RefCell;
+use std::path::PathBuf;
 
 use crate::{
-    configuration::{Configuration, ConfigurationError},
-    model::{Folder, FolderList},
-    security::{SecurityError, TenantSession},
+    client::{ClientError, DeviceAuthorization, PhysnaHttpClient, DEFAULT_TOKEN_REFRESH_WINDOW},
+    configuration::{Configuration, ConfigurationError, TenantConfiguration},
+    model::{Folder, FolderList, User},
+    security::{unix_time_now, AuthStatus, SecurityError, TenantSession},
 };
 use log::trace;
+use reqwest::StatusCode;
 
 /// Error emmitted by the Api
 ///
@@ -30,6 +33,132 @@ pub enum ApiError {
     UnsupportedOperation,
 }
 
+impl ApiError {
+    /// Returns the HTTP status code of the failed request, if the error was caused by one
+    pub fn http_status(&self) -> Option<StatusCode> {
+        match self {
+            ApiError::SecurityError {
+                cause: SecurityError::ClientError(ClientError::UnexpectedResponse(status)),
+            } => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+/// Process exit codes used by PCLI2
+///
+/// Where possible, the values follow the BSD sysexits convention used throughout the application
+/// (see the `exitcode` crate), so that automation can tell the failure classes apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcliExitCode {
+    /// The input data was incorrect in some way
+    DataError,
+    /// The requested resource does not exist (HTTP 404)
+    NotFound,
+    /// The service is unavailable or failed (HTTP 5xx)
+    ServerError,
+    /// The request was throttled and may be retried later (HTTP 429)
+    RateLimited,
+    /// The server sent an unexpected response
+    ProtocolError,
+    /// Authentication or authorization failed (HTTP 401/403)
+    AuthError,
+    /// The configuration is missing or invalid
+    ConfigError,
+    /// The request conflicts with the current state of the resource (HTTP 409)
+    Conflict,
+    /// The credential store (e.g. the keyring) could not be used
+    CredentialStoreError,
+    /// There is no token and no credentials to obtain one
+    TokenMissing,
+    /// The token expired and cannot be renewed
+    TokenExpired,
+    /// The token was not granted a required scope
+    InsufficientScope,
+    /// The identity provider rejected the renewal of the token
+    RefreshFailed,
+}
+
+impl PcliExitCode {
+    /// Classifies an HTTP status code
+    pub fn from_http_status(status: StatusCode) -> PcliExitCode {
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => PcliExitCode::AuthError,
+            StatusCode::NOT_FOUND => PcliExitCode::NotFound,
+            StatusCode::CONFLICT => PcliExitCode::Conflict,
+            StatusCode::TOO_MANY_REQUESTS => PcliExitCode::RateLimited,
+            status if status.is_server_error() => PcliExitCode::ServerError,
+            _ => PcliExitCode::ProtocolError,
+        }
+    }
+}
+
+impl From<PcliExitCode> for exitcode::ExitCode {
+    fn from(code: PcliExitCode) -> exitcode::ExitCode {
+        match code {
+            PcliExitCode::DataError => exitcode::DATAERR,
+            PcliExitCode::NotFound => exitcode::NOINPUT,
+            PcliExitCode::ServerError => exitcode::UNAVAILABLE,
+            PcliExitCode::RateLimited => exitcode::TEMPFAIL,
+            PcliExitCode::ProtocolError => exitcode::PROTOCOL,
+            PcliExitCode::AuthError => exitcode::NOPERM,
+            PcliExitCode::ConfigError => exitcode::CONFIG,
+            // sysexits has no equivalent, so use the first value after its range
+            PcliExitCode::Conflict => 79,
+            PcliExitCode::CredentialStoreError => 80,
+            PcliExitCode::TokenMissing => 81,
+            PcliExitCode::TokenExpired => 82,
+            PcliExitCode::InsufficientScope => 83,
+            PcliExitCode::RefreshFailed => 84,
+        }
+    }
+}
+
+impl From<&ApiError> for PcliExitCode {
+    fn from(error: &ApiError) -> PcliExitCode {
+        if let Some(status) = error.http_status() {
+            return PcliExitCode::from_http_status(status);
+        }
+
+        match error {
+            ApiError::ConfigurationError {
+                cause: ConfigurationError::KeyringError(_),
+            }
+            | ApiError::SecurityError {
+                cause: SecurityError::KeyrinError(_),
+            }
+            | ApiError::SecurityError {
+                cause:
+                    SecurityError::SecurityError {
+                        cause: ConfigurationError::KeyringError(_),
+                    },
+            } => PcliExitCode::CredentialStoreError,
+            ApiError::UnknownTenant { .. }
+            | ApiError::InvalidTenant(_)
+            | ApiError::ConfigurationError { .. } => PcliExitCode::ConfigError,
+            ApiError::SecurityError { cause } => match cause {
+                SecurityError::ClientError(ClientError::InvalidResponse(_)) => {
+                    PcliExitCode::ProtocolError
+                }
+                SecurityError::ClientError(ClientError::HttpError(_))
+                | SecurityError::ClientError(ClientError::FailedToObtainToken) => {
+                    PcliExitCode::ServerError
+                }
+                SecurityError::SecurityError { .. }
+                | SecurityError::ClientError(ClientError::InvalidNetworkConfiguration(_)) => {
+                    PcliExitCode::ConfigError
+                }
+                SecurityError::TokenMissing => PcliExitCode::TokenMissing,
+                SecurityError::TokenExpired => PcliExitCode::TokenExpired,
+                SecurityError::InsufficientScope { .. } => PcliExitCode::InsufficientScope,
+                SecurityError::RefreshFailed(_) => PcliExitCode::RefreshFailed,
+                _ => PcliExitCode::AuthError,
+            },
+            ApiError::UnsupportedOperation => PcliExitCode::DataError,
+        }
+    }
+}
+
 pub struct UnauthorizedApi {}
 pub struct AuthorizedApi {}
 
@@ -37,6 +166,9 @@ pub struct AuthorizedApi {}
 ///
 pub struct Api<State = UnauthorizedApi> {
     configuration: RefCell<Configuration>,
+    strict_models: bool,
+    token_refresh_window: u64,
+    client_identity: Option<(Option<PathBuf>, Option<PathBuf>)>,
     state: std::marker::PhantomData<State>,
 }
 
@@ -47,21 +179,79 @@ impl Api {
     pub fn new(configuration: &RefCell<Configuration>) -> Api {
         Api {
             configuration: configuration.clone(),
+            strict_models: false,
+            token_refresh_window: DEFAULT_TOKEN_REFRESH_WINDOW,
+            client_identity: None,
             state: std::marker::PhantomData::<UnauthorizedApi>,
         }
     }
 
+    /// Enables strict validation of the API responses against the model
+    ///
+    pub fn set_strict_models(&mut self, strict_models: bool) {
+        self.strict_models = strict_models;
+    }
+
+    /// Sets how many seconds before expiration a stored token is refreshed
+    ///
+    pub fn set_token_refresh_window(&mut self, token_refresh_window: u64) {
+        self.token_refresh_window = token_refresh_window;
+    }
+
+    /// Overrides the client certificate and private key of the network configuration for mutual TLS
+    ///
+    pub fn set_client_identity(
+        &mut self,
+        client_cert: Option<PathBuf>,
+        client_key: Option<PathBuf>,
+    ) {
+        self.client_identity = Some((client_cert, client_key));
+    }
+
+    /// Creates the HTTP client for a tenant
+    ///
+    /// This is the only place where clients are constructed, so that all commands share the same client setup.
+    fn client(
+        &self,
+        tenant_configuration: TenantConfiguration,
+    ) -> Result<PhysnaHttpClient, ApiError> {
+        let mut network = self.configuration.borrow().network();
+        if let Some((client_cert, client_key)) = &self.client_identity {
+            network.set_client_identity(client_cert.clone(), client_key.clone());
+        }
+        let mut client = PhysnaHttpClient::with_network(tenant_configuration, &network)
+            .map_err(SecurityError::from)?;
+        client.set_strict_models(self.strict_models);
+        client.set_token_refresh_window(self.token_refresh_window);
+        client.set_configuration_path(self.configuration.borrow().file_path().ok());
+        Ok(client)
+    }
+
     pub fn login(&self, tenant_id: &String) -> Result<TenantSession, ApiError> {
         let tenant_configuration = &self.configuration.borrow().tenant(tenant_id);
         match tenant_configuration {
             Some(tenant_configuration) => {
-                let session = TenantSession::login(tenant_configuration.to_owned())?;
+                let session = TenantSession::login_with_client(
+                    self.client(tenant_configuration.to_owned())?,
+                )?;
                 Ok(session)
             }
             None => Err(ApiError::InvalidTenant(tenant_id.to_owned())),
         }
     }
 
+    /// Logs in interactively with the device authorization flow, for users without client credentials
+    ///
+    pub fn device_login<F: Fn(&DeviceAuthorization)>(
+        &self,
+        tenant_id: &String,
+        prompt: F,
+    ) -> Result<TenantSession, ApiError> {
+        let tenant_configuration = self.configuration.borrow().validate_tenant(tenant_id)?;
+        let session = TenantSession::device_login(self.client(tenant_configuration)?, prompt)?;
+        Ok(session)
+    }
+
     pub fn logoff(&self, tenant_id: &String) -> Result<(), ApiError> {
         let tenant_configuration = &self.configuration.borrow().tenant(tenant_id);
         match tenant_configuration {
@@ -73,6 +263,85 @@ impl Api {
         }
     }
 
+    /// Removes the stored tokens of the tenant, and also its client secret if requested
+    ///
+    pub fn logout(&self, tenant_id: &String, include_secret: bool) -> Result<(), ApiError> {
+        let tenant_configuration = self.configuration.borrow().validate_tenant(tenant_id)?;
+        TenantSession::wipe(&tenant_configuration, include_secret)?;
+        Ok(())
+    }
+
+    /// Removes the stored tokens of the tenants of all profiles, and also their client secrets if requested
+    ///
+    pub fn logout_all(&self, include_secret: bool) -> Result<(), ApiError> {
+        for tenant_configuration in self.configuration.borrow().all_tenant_configurations() {
+            trace!(
+                "Removing the credentials of tenant \"{}\"...",
+                tenant_configuration.keyring_id()
+            );
+            TenantSession::wipe(&tenant_configuration, include_secret)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the stored token of the tenant with a new one and reports on it
+    ///
+    pub fn refresh_token(&self, tenant_id: &String) -> Result<AuthStatus, ApiError> {
+        let tenant_configuration = self.configuration.borrow().validate_tenant(tenant_id)?;
+        let session = TenantSession::refresh(self.client(tenant_configuration.clone())?)?;
+        let can_refresh = TenantSession::can_refresh(&tenant_configuration);
+
+        Ok(AuthStatus::new(
+            tenant_id,
+            &tenant_configuration,
+            session.token().as_deref(),
+            can_refresh,
+            unix_time_now(),
+        ))
+    }
+
+    /// Fails with the first of the required scopes that the token of the tenant was not granted
+    ///
+    /// The token is obtained or refreshed as for any other command, so that the check reflects what the next command would use.
+    pub fn check_scopes(&self, tenant_id: &String, required: &[&str]) -> Result<(), ApiError> {
+        trace!(
+            "Checking scopes {:?} for tenant \"{}\"...",
+            required,
+            tenant_id
+        );
+        let session = self.login(tenant_id)?;
+        session.require_scopes(required)?;
+        Ok(())
+    }
+
+    /// Reports on the credentials stored for the tenant without contacting the identity provider
+    ///
+    pub fn auth_status(&self, tenant_id: &String) -> Result<AuthStatus, ApiError> {
+        let tenant_configuration = self.configuration.borrow().validate_tenant(tenant_id)?;
+        let token = TenantSession::stored_token(&tenant_configuration)?;
+        let can_refresh = TenantSession::can_refresh(&tenant_configuration);
+        let now = unix_time_now();
+
+        Ok(AuthStatus::new(
+            tenant_id,
+            &tenant_configuration,
+            token.as_deref(),
+            can_refresh,
+            now,
+        ))
+    }
+
+    /// Returns the user the session of the tenant belongs to
+    ///
+    pub fn current_user(&self, tenant_id: &String) -> Result<User, ApiError> {
+        trace!(
+            "Requesting the current user for tenant \"{}\"...",
+            tenant_id
+        );
+        let session = self.login(tenant_id)?;
+        Ok(session.user()?)
+    }
+
     /// Returns the list of folders currently available for the specified tenant
     ///
     pub fn list_folders(&self, tenant_id: &String) -> Result<FolderList, ApiError> {
@@ -99,3 +368,99 @@ impl Api {
         // Err(ApiError::UnsupportedOperation)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::security::KeyringError;
+
+    #[test]
+    fn test_exit_code_from_http_status() {
+        let classify = |status: u16| {
+            let error = ApiError::from(SecurityError::ClientError(
+                ClientError::UnexpectedResponse(StatusCode::from_u16(status).unwrap()),
+            ));
+            PcliExitCode::from(&error)
+        };
+
+        assert_eq!(PcliExitCode::AuthError, classify(401));
+        assert_eq!(PcliExitCode::AuthError, classify(403));
+        assert_eq!(PcliExitCode::NotFound, classify(404));
+        assert_eq!(PcliExitCode::Conflict, classify(409));
+        assert_eq!(PcliExitCode::RateLimited, classify(429));
+        assert_eq!(PcliExitCode::ServerError, classify(500));
+        assert_eq!(PcliExitCode::ServerError, classify(503));
+        assert_eq!(PcliExitCode::ProtocolError, classify(418));
+    }
+
+    #[test]
+    fn test_exit_code_of_credential_store_failure() {
+        let error = ApiError::from(SecurityError::from(KeyringError::PassphraseRequired));
+        assert_eq!(
+            PcliExitCode::CredentialStoreError,
+            PcliExitCode::from(&error)
+        );
+
+        let error = ApiError::from(ConfigurationError::from(KeyringError::ReadOnly));
+        assert_eq!(
+            PcliExitCode::CredentialStoreError,
+            PcliExitCode::from(&error)
+        );
+    }
+
+    #[test]
+    fn test_exit_code_of_authentication_failure() {
+        let classify = |error: SecurityError| PcliExitCode::from(&ApiError::from(error));
+        assert_eq!(
+            PcliExitCode::TokenMissing,
+            classify(SecurityError::TokenMissing)
+        );
+        assert_eq!(
+            PcliExitCode::TokenExpired,
+            classify(SecurityError::TokenExpired)
+        );
+        assert_eq!(
+            PcliExitCode::InsufficientScope,
+            classify(SecurityError::InsufficientScope {
+                scope: "tenantApp".to_string()
+            })
+        );
+        assert_eq!(
+            PcliExitCode::RefreshFailed,
+            classify(SecurityError::RefreshFailed(
+                ClientError::FailedToObtainToken
+            ))
+        );
+        assert_eq!(
+            PcliExitCode::AuthError,
+            classify(SecurityError::AccessDenied)
+        );
+    }
+
+    #[test]
+    fn test_exit_code_values_are_distinct() {
+        let codes: Vec<exitcode::ExitCode> = vec![
+            PcliExitCode::DataError,
+            PcliExitCode::NotFound,
+            PcliExitCode::ServerError,
+            PcliExitCode::RateLimited,
+            PcliExitCode::ProtocolError,
+            PcliExitCode::AuthError,
+            PcliExitCode::ConfigError,
+            PcliExitCode::Conflict,
+            PcliExitCode::CredentialStoreError,
+            PcliExitCode::TokenMissing,
+            PcliExitCode::TokenExpired,
+            PcliExitCode::InsufficientScope,
+            PcliExitCode::RefreshFailed,
+        ]
+        .into_iter()
+        .map(exitcode::ExitCode::from)
+        .collect();
+
+        let mut unique = codes.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(codes.len(), unique.len());
+    }
+}
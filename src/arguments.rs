@@ -0,0 +1,121 @@
+use std::time::Duration;
+use thiserror::Error;
+
+/// Error parsing a human friendly argument value
+///
+/// Clap prefixes the message with the offending flag and value.
+#[derive(Debug, Error, PartialEq)]
+pub enum ArgumentError {
+    #[error("expected a whole number followed by an optional unit")]
+    MissingNumber,
+    #[error("unknown duration unit {0:?}, expected one of ms, s, m, h, d, w")]
+    UnknownDurationUnit(String),
+    #[error("unknown size unit {0:?}, expected one of B, KB, MB, GB, TB, KiB, MiB, GiB, TiB")]
+    UnknownSizeUnit(String),
+    #[error("the value is too large")]
+    Overflow,
+}
+
+/// Splits a value like "90s" into its number and unit
+fn split_unit(value: &str) -> Result<(u64, String), ArgumentError> {
+    let value = value.trim();
+    let position = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(position);
+    // a fraction like "1.5h" is not a number followed by the unit ".5h"
+    if unit.starts_with(['.', ',']) {
+        return Err(ArgumentError::MissingNumber);
+    }
+    match number.parse::<u64>() {
+        Ok(number) => Ok((number, unit.trim().to_lowercase())),
+        Err(_) => Err(ArgumentError::MissingNumber),
+    }
+}
+
+/// Parses a duration like "90s", "15m" or "7d"
+///
+/// A number without a unit is taken as seconds, so that existing numeric flags keep working.
+pub fn parse_duration(value: &str) -> Result<Duration, ArgumentError> {
+    let (number, unit) = split_unit(value)?;
+    let millis = match unit.as_str() {
+        "ms" => 1,
+        "" | "s" => 1_000,
+        "m" => 60_000,
+        "h" => 3_600_000,
+        "d" => 86_400_000,
+        "w" => 604_800_000,
+        _ => return Err(ArgumentError::UnknownDurationUnit(unit)),
+    };
+    match number.checked_mul(millis) {
+        Some(millis) => Ok(Duration::from_millis(millis)),
+        None => Err(ArgumentError::Overflow),
+    }
+}
+
+/// Parses a size in bytes like "512", "250MB" or "4GiB"
+///
+/// The SI units are powers of 1000 and the IEC units powers of 1024. The unit is not case sensitive.
+#[allow(dead_code)]
+pub fn parse_size(value: &str) -> Result<u64, ArgumentError> {
+    let (number, unit) = split_unit(value)?;
+    let multiplier: u64 = match unit.as_str() {
+        "" | "b" => 1,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => return Err(ArgumentError::UnknownSizeUnit(unit)),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or(ArgumentError::Overflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(Ok(Duration::from_secs(60)), parse_duration("60"));
+        assert_eq!(Ok(Duration::from_secs(90)), parse_duration("90s"));
+        assert_eq!(Ok(Duration::from_millis(250)), parse_duration("250ms"));
+        assert_eq!(Ok(Duration::from_secs(900)), parse_duration("15m"));
+        assert_eq!(Ok(Duration::from_secs(7200)), parse_duration("2H"));
+        assert_eq!(Ok(Duration::from_secs(604_800)), parse_duration("7d"));
+        assert_eq!(Ok(Duration::from_secs(604_800)), parse_duration("1w"));
+
+        assert_eq!(Err(ArgumentError::MissingNumber), parse_duration("s"));
+        assert_eq!(Err(ArgumentError::MissingNumber), parse_duration("-5s"));
+        assert_eq!(Err(ArgumentError::MissingNumber), parse_duration("1.5h"));
+        assert_eq!(
+            Err(ArgumentError::UnknownDurationUnit("y".to_string())),
+            parse_duration("1y")
+        );
+        assert_eq!(
+            Err(ArgumentError::Overflow),
+            parse_duration("18446744073709551615w")
+        );
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(Ok(512), parse_size("512"));
+        assert_eq!(Ok(512), parse_size("512B"));
+        assert_eq!(Ok(250_000_000), parse_size("250MB"));
+        assert_eq!(Ok(250_000_000), parse_size("250 mb"));
+        assert_eq!(Ok(4 * 1024 * 1024 * 1024), parse_size("4GiB"));
+
+        assert_eq!(Err(ArgumentError::MissingNumber), parse_size(""));
+        assert_eq!(Err(ArgumentError::MissingNumber), parse_size("1.5GB"));
+        assert_eq!(
+            Err(ArgumentError::UnknownSizeUnit("pb".to_string())),
+            parse_size("1PB")
+        );
+    }
+}
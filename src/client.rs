@@ -1,8 +1,11 @@
+use std::path::PathBuf;
 use std::time::Duration;
 
-use crate::configuration::TenantConfiguration;
+use crate::configuration::{NetworkConfiguration, TenantConfiguration};
+use crate::model::{self, Model, ModelError};
+use crate::security::env_var;
 use base64::{engine::general_purpose, Engine};
-use log::trace;
+use log::{error, trace, warn};
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -21,33 +24,280 @@ pub enum ClientError {
     HttpError(#[from] reqwest::Error),
     #[error("unexpected response from server: {0}")]
     UnexpectedResponse(StatusCode),
+    #[error("the identity provider rejected the request: {0}")]
+    AuthorizationError(String),
+    #[error("invalid response from server: {0}")]
+    InvalidResponse(#[from] ModelError),
+    #[error("invalid network configuration: {0}")]
+    InvalidNetworkConfiguration(String),
 }
 
+/// Environment variable overriding the configured proxy URL
+pub const ENV_PROXY_URL: &str = "PCLI2_PROXY_URL";
+/// Environment variable overriding the configured proxy user name
+pub const ENV_PROXY_USERNAME: &str = "PCLI2_PROXY_USERNAME";
+/// Environment variable providing the password of the proxy user
+pub const ENV_PROXY_PASSWORD: &str = "PCLI2_PROXY_PASSWORD";
+/// Environment variable overriding the configured hosts that bypass the proxy
+pub const ENV_NO_PROXY: &str = "PCLI2_NO_PROXY";
+/// Environment variable overriding the configured CA certificate file
+pub const ENV_CA_FILE: &str = "PCLI2_CA_FILE";
+/// Environment variable overriding the configured client certificate file for mutual TLS
+pub const ENV_CLIENT_CERT: &str = "PCLI2_CLIENT_CERT";
+/// Environment variable overriding the configured private key file of the client certificate
+pub const ENV_CLIENT_KEY: &str = "PCLI2_CLIENT_KEY";
+
+/// Scope granting access to the tenant API
+pub const SCOPE_TENANT_APP: &str = "tenantApp";
+/// Scope exposing the user roles in the token
+pub const SCOPE_ROLES: &str = "roles";
+
+/// Default number of seconds before expiration at which a token is refreshed
+pub const DEFAULT_TOKEN_REFRESH_WINDOW: u64 = 60;
+
+/// Scope requesting a refresh token for interactive logins
+pub const SCOPE_OFFLINE_ACCESS: &str = "offline_access";
+
+/// OAuth grant type of the device authorization flow (RFC 8628)
+const GRANT_TYPE_DEVICE_CODE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 struct AuthenticationResponse {
     token_type: String, //e.g. "Bearer"
     expires_in: u64,    //e.g. 36000
     access_token: String,
     scope: String, //e.g. "tenantApp"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id_token: Option<String>,
+}
+
+impl Model for AuthenticationResponse {}
+
+/// Error response of the OAuth token endpoint
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct OAuthErrorResponse {
+    error: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error_description: Option<String>,
+}
+
+/// Tokens issued by the identity provider
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenGrant {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+}
+
+/// Response to a device authorization request (RFC 8628)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceAuthorization {
+    pub device_code: String,
+    /// Code the user enters on the verification page
+    pub user_code: String,
+    pub verification_uri: String,
+    /// Verification page with the user code already filled in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verification_uri_complete: Option<String>,
+    /// Seconds until the device code expires
+    pub expires_in: u64,
+    /// Minimum number of seconds between polls of the token endpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<u64>,
+}
+
+impl Model for DeviceAuthorization {}
+
+/// Outcome of polling the token endpoint during the device authorization flow
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceTokenStatus {
+    /// The user has not completed the login yet
+    Pending,
+    /// The client is polling too often and must increase the interval
+    SlowDown,
+    Granted(TokenGrant),
 }
 
+/// HTTP client for a single tenant
+///
+/// The underlying connection pool is created once and reused for all requests made through this client.
 pub struct PhysnaHttpClient {
     tenant_configuration: TenantConfiguration,
+    strict_models: bool,
+    token_refresh_window: u64,
+    /// Configuration file in use, next to which the token refresh locks are kept
+    configuration_path: Option<PathBuf>,
+    http_client: reqwest::blocking::Client,
 }
 
 impl PhysnaHttpClient {
-    pub fn new(tenant_configuration: TenantConfiguration) -> PhysnaHttpClient {
-        PhysnaHttpClient {
+    pub fn new(tenant_configuration: TenantConfiguration) -> Result<PhysnaHttpClient, ClientError> {
+        Self::with_network(tenant_configuration, &NetworkConfiguration::default())
+    }
+
+    /// Creates a client that connects through the configured proxy, trusts the configured CA certificates
+    /// and presents the configured client certificate
+    ///
+    /// Without a configured proxy, the standard `HTTP_PROXY`/`HTTPS_PROXY` environment variables still apply.
+    pub fn with_network(
+        tenant_configuration: TenantConfiguration,
+        network: &NetworkConfiguration,
+    ) -> Result<PhysnaHttpClient, ClientError> {
+        let mut builder = reqwest::blocking::Client::builder().timeout(Duration::from_secs(20));
+
+        let proxy_url = match env_var(ENV_PROXY_URL) {
+            Some(proxy_url) => match url::Url::parse(&proxy_url) {
+                Ok(proxy_url) => Some(proxy_url),
+                Err(e) => {
+                    return Err(ClientError::InvalidNetworkConfiguration(format!(
+                        "invalid proxy URL in {}: {}",
+                        ENV_PROXY_URL, e
+                    )))
+                }
+            },
+            None => network.proxy_url(),
+        };
+        if let Some(proxy_url) = proxy_url {
+            trace!(
+                "Connecting through the proxy at {}...",
+                proxy_url.host_str().unwrap_or_default()
+            );
+            let mut proxy = reqwest::Proxy::all(proxy_url.clone()).map_err(|e| {
+                ClientError::InvalidNetworkConfiguration(format!("invalid proxy URL: {}", e))
+            })?;
+            if let Some(username) = env_var(ENV_PROXY_USERNAME).or_else(|| network.proxy_username())
+            {
+                let password = env_var(ENV_PROXY_PASSWORD).unwrap_or_else(|| {
+                    warn!(
+                        "{} is not set, authenticating to the proxy as \"{}\" without a password",
+                        ENV_PROXY_PASSWORD, username
+                    );
+                    String::new()
+                });
+                proxy = proxy.basic_auth(&username, &password);
+            }
+            let no_proxy = env_var(ENV_NO_PROXY).or_else(|| network.no_proxy());
+            if let Some(no_proxy) = no_proxy {
+                proxy = proxy.no_proxy(reqwest::NoProxy::from_string(&no_proxy));
+            }
+            builder = builder.proxy(proxy);
+        }
+
+        let ca_file = env_var(ENV_CA_FILE)
+            .map(std::path::PathBuf::from)
+            .or_else(|| network.ca_file());
+        if let Some(ca_file) = ca_file {
+            trace!("Trusting the CA certificates in {}...", ca_file.display());
+            let invalid_ca_file = |message: String| {
+                ClientError::InvalidNetworkConfiguration(format!(
+                    "cannot read CA certificates from {}: {}",
+                    ca_file.display(),
+                    message
+                ))
+            };
+            let pem = std::fs::read(&ca_file).map_err(|e| invalid_ca_file(e.to_string()))?;
+            let certificates = reqwest::Certificate::from_pem_bundle(&pem)
+                .map_err(|e| invalid_ca_file(e.to_string()))?;
+            if certificates.is_empty() {
+                return Err(invalid_ca_file("no certificates found".to_string()));
+            }
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        let client_cert = env_var(ENV_CLIENT_CERT)
+            .map(std::path::PathBuf::from)
+            .or_else(|| network.client_cert());
+        let client_key = env_var(ENV_CLIENT_KEY)
+            .map(std::path::PathBuf::from)
+            .or_else(|| network.client_key());
+        match (client_cert, client_key) {
+            (Some(client_cert), Some(client_key)) => {
+                trace!(
+                    "Presenting the client certificate {}...",
+                    client_cert.display()
+                );
+                let invalid_identity = |message: String| {
+                    ClientError::InvalidNetworkConfiguration(format!(
+                        "cannot use the client certificate {}: {}",
+                        client_cert.display(),
+                        message
+                    ))
+                };
+                let cert =
+                    std::fs::read(&client_cert).map_err(|e| invalid_identity(e.to_string()))?;
+                let key =
+                    std::fs::read(&client_key).map_err(|e| invalid_identity(e.to_string()))?;
+                let identity = reqwest::Identity::from_pkcs8_pem(&cert, &key)
+                    .map_err(|e| invalid_identity(e.to_string()))?;
+                builder = builder.identity(identity);
+            }
+            (None, None) => (),
+            _ => {
+                return Err(ClientError::InvalidNetworkConfiguration(
+                    "a client certificate requires both the certificate and the private key"
+                        .to_string(),
+                ))
+            }
+        }
+
+        let http_client = builder.build()?;
+
+        Ok(PhysnaHttpClient {
             tenant_configuration,
+            strict_models: false,
+            token_refresh_window: DEFAULT_TOKEN_REFRESH_WINDOW,
+            configuration_path: None,
+            http_client,
+        })
+    }
+
+    pub fn tenant_configuration(&self) -> TenantConfiguration {
+        self.tenant_configuration.clone()
+    }
+
+    /// When enabled, responses containing properties unknown to the model are rejected
+    pub fn set_strict_models(&mut self, strict_models: bool) {
+        self.strict_models = strict_models;
+    }
+
+    /// Seconds before expiration at which a stored token is refreshed proactively
+    pub fn token_refresh_window(&self) -> u64 {
+        self.token_refresh_window
+    }
+
+    pub fn set_token_refresh_window(&mut self, token_refresh_window: u64) {
+        self.token_refresh_window = token_refresh_window;
+    }
+
+    /// Configuration file in use, or None for the default location
+    pub fn configuration_path(&self) -> Option<PathBuf> {
+        self.configuration_path.clone()
+    }
+
+    pub fn set_configuration_path(&mut self, configuration_path: Option<PathBuf>) {
+        self.configuration_path = configuration_path;
+    }
+
+    fn parse_response<T: Model>(&self, body: &str) -> Result<T, ClientError> {
+        match model::from_json::<T>(body, self.strict_models) {
+            Ok(value) => Ok(value),
+            Err(e) => {
+                error!("Failed to parse the server response: {}", e);
+                trace!("Response body: {}", body);
+                Err(ClientError::InvalidResponse(e))
+            }
         }
     }
 
     pub fn request_new_token_from_provider(
         &self,
+        client_id: String,
         client_secret: String,
     ) -> Result<String, ClientError> {
         let tenant = self.tenant_configuration.tenant_id();
-        let client_id = self.tenant_configuration.client_id();
 
         trace!(
             "Requesting new token from provider for tenant {}...",
@@ -93,45 +343,256 @@ impl PhysnaHttpClient {
         let mut authorization_header_value = String::from("Basic ");
         authorization_header_value.push_str(encoded_credentials.as_str());
 
+        let scope = [SCOPE_TENANT_APP, SCOPE_ROLES].join(" ");
         let params = [
             ("grant_type", "client_credentials"),
-            ("scope", "tenantApp roles"),
+            ("scope", scope.as_str()),
         ];
 
-        // Create the HTTP client instance
-        //let client = reqwest::Client::new();
-        let client = reqwest::blocking::Client::builder()
-            .timeout(Duration::from_secs(20))
-            .build()?;
+        let response =
+            self.post_token_request(&params, Some(authorization_header_value.as_str()))?;
+        Ok(response.access_token)
+    }
 
-        let url = self.tenant_configuration.oidc_url();
-        let response = client
+    /// Returns the device authorization endpoint of the identity provider
+    ///
+    /// The endpoint is derived from the configured token endpoint, e.g.
+    /// `https://physna.okta.com/oauth2/default/v1/token` becomes
+    /// `https://physna.okta.com/oauth2/default/v1/device/authorize`.
+    fn device_authorization_url(&self) -> Result<url::Url, ClientError> {
+        let mut url = self.tenant_configuration.oidc_url();
+        let path = url.path().trim_end_matches('/').to_string();
+        match path.strip_suffix("/token") {
+            Some(base) => {
+                url.set_path(&format!("{}/device/authorize", base));
+                Ok(url)
+            }
+            None => Err(ClientError::AuthorizationError(format!(
+                "cannot derive the device authorization endpoint from {}",
+                url
+            ))),
+        }
+    }
+
+    /// Starts the device authorization flow for an interactive login
+    pub fn request_device_authorization(
+        &self,
+        client_id: String,
+    ) -> Result<DeviceAuthorization, ClientError> {
+        if client_id.is_empty() {
+            return Err(ClientError::InvalidClientId);
+        }
+
+        let scope = [SCOPE_TENANT_APP, SCOPE_ROLES, SCOPE_OFFLINE_ACCESS].join(" ");
+        let params = [("client_id", client_id.as_str()), ("scope", scope.as_str())];
+
+        let url = self.device_authorization_url()?;
+        trace!("Requesting device authorization from {}...", url);
+        let response = self
+            .http_client
             .post(url)
-            .header("Authorization", authorization_header_value.as_str())
-            .header("cache-control", "no-cache")
+            .header("accept", "application/json")
             .form(&params)
-            .send();
+            .send()?;
+
+        let status = response.status();
+        let body = response.text()?;
+        match status {
+            StatusCode::OK => self.parse_response(&body),
+            _ => Err(Self::oauth_error(status, &body)),
+        }
+    }
+
+    /// Polls the token endpoint once for the outcome of a device authorization
+    pub fn poll_device_token(
+        &self,
+        client_id: String,
+        device_code: &str,
+    ) -> Result<DeviceTokenStatus, ClientError> {
+        let params = [
+            ("grant_type", GRANT_TYPE_DEVICE_CODE),
+            ("device_code", device_code),
+            ("client_id", client_id.as_str()),
+        ];
+
+        match self.post_token_request(&params, None) {
+            Ok(response) => Ok(DeviceTokenStatus::Granted(TokenGrant {
+                access_token: response.access_token,
+                refresh_token: response.refresh_token,
+            })),
+            Err(ClientError::AuthorizationError(error)) if error == "authorization_pending" => {
+                Ok(DeviceTokenStatus::Pending)
+            }
+            Err(ClientError::AuthorizationError(error)) if error == "slow_down" => {
+                Ok(DeviceTokenStatus::SlowDown)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Exchanges a refresh token obtained during an interactive login for new tokens
+    pub fn refresh_token(
+        &self,
+        client_id: String,
+        refresh_token: &str,
+    ) -> Result<TokenGrant, ClientError> {
+        let scope = [SCOPE_TENANT_APP, SCOPE_ROLES, SCOPE_OFFLINE_ACCESS].join(" ");
+        let params = [
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+            ("client_id", client_id.as_str()),
+            ("scope", scope.as_str()),
+        ];
+
+        let response = self.post_token_request(&params, None)?;
+        Ok(TokenGrant {
+            access_token: response.access_token,
+            // the provider may keep the refresh token unchanged
+            refresh_token: response
+                .refresh_token
+                .or_else(|| Some(refresh_token.to_string())),
+        })
+    }
+
+    fn post_token_request(
+        &self,
+        params: &[(&str, &str)],
+        authorization: Option<&str>,
+    ) -> Result<AuthenticationResponse, ClientError> {
+        let url = self.tenant_configuration.oidc_url();
+        let mut request = self
+            .http_client
+            .post(url)
+            .header("accept", "application/json")
+            .header("cache-control", "no-cache");
+        if let Some(authorization) = authorization {
+            request = request.header("Authorization", authorization);
+        }
 
-        match response {
+        match request.form(params).send() {
             Ok(response) => {
                 let status = response.status();
-
-                if status == StatusCode::OK {
-                    let response_text = response.text();
-                    match response_text {
-                        Ok(response_text) => {
-                            let response: AuthenticationResponse =
-                                serde_yaml::from_str(&response_text).unwrap();
-                            let token = response.access_token;
-                            Ok(token)
-                        }
-                        Err(_) => Err(ClientError::UnexpectedResponse(status)),
+                match response.text() {
+                    Ok(response_text) if status == StatusCode::OK => {
+                        self.parse_response(&response_text)
                     }
-                } else {
-                    Err(ClientError::UnexpectedResponse(status))
+                    Ok(response_text) => Err(Self::oauth_error(status, &response_text)),
+                    Err(_) => Err(ClientError::UnexpectedResponse(status)),
                 }
             }
             Err(_) => Err(ClientError::FailedToObtainToken),
         }
     }
+
+    /// Reports the OAuth error code of a failed request, if the provider sent one
+    ///
+    /// Only 400 responses carry a meaningful code; anything else is reported by its status.
+    fn oauth_error(status: StatusCode, body: &str) -> ClientError {
+        match serde_json::from_str::<OAuthErrorResponse>(body) {
+            Ok(error) if status == StatusCode::BAD_REQUEST => {
+                trace!(
+                    "OAuth error {}: {}",
+                    error.error,
+                    error.error_description.unwrap_or_default()
+                );
+                ClientError::AuthorizationError(error.error)
+            }
+            _ => ClientError::UnexpectedResponse(status),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(oidc_url: &str) -> PhysnaHttpClient {
+        PhysnaHttpClient::new(TenantConfiguration::new(
+            "my_tenant".to_string(),
+            url::Url::parse("https://my_tenant.physna.com/api/v2").unwrap(),
+            url::Url::parse(oidc_url).unwrap(),
+            "my_client_id".to_string(),
+        ))
+        .unwrap()
+    }
+
+    fn network_client(network: &NetworkConfiguration) -> Result<PhysnaHttpClient, ClientError> {
+        PhysnaHttpClient::with_network(
+            TenantConfiguration::new(
+                "my_tenant".to_string(),
+                url::Url::parse("https://my_tenant.physna.com/api/v2").unwrap(),
+                url::Url::parse("https://authentication.com").unwrap(),
+                "my_client_id".to_string(),
+            ),
+            network,
+        )
+    }
+
+    #[test]
+    fn test_invalid_network_configuration() {
+        let proxy = NetworkConfiguration::new(
+            Some(url::Url::parse("ftp://proxy.corp:21").unwrap()),
+            None,
+            None,
+            None,
+        );
+        assert!(matches!(
+            network_client(&proxy),
+            Err(ClientError::InvalidNetworkConfiguration(_))
+        ));
+
+        let directory = tempfile::tempdir().unwrap();
+        let missing =
+            NetworkConfiguration::new(None, None, None, Some(directory.path().join("missing.pem")));
+        assert!(matches!(
+            network_client(&missing),
+            Err(ClientError::InvalidNetworkConfiguration(_))
+        ));
+
+        let ca_file = directory.path().join("corp.pem");
+        std::fs::write(&ca_file, "not a certificate").unwrap();
+        let invalid = NetworkConfiguration::new(None, None, None, Some(ca_file));
+        assert!(matches!(
+            network_client(&invalid),
+            Err(ClientError::InvalidNetworkConfiguration(_))
+        ));
+
+        assert!(network_client(&NetworkConfiguration::default()).is_ok());
+    }
+
+    #[test]
+    fn test_device_authorization_url() {
+        let url = client("https://physna.okta.com/oauth2/default/v1/token")
+            .device_authorization_url()
+            .unwrap();
+        assert_eq!(
+            "https://physna.okta.com/oauth2/default/v1/device/authorize",
+            url.as_str()
+        );
+
+        assert!(client("https://physna.okta.com/oauth2/default")
+            .device_authorization_url()
+            .is_err());
+    }
+
+    #[test]
+    fn test_oauth_error() {
+        match PhysnaHttpClient::oauth_error(
+            StatusCode::BAD_REQUEST,
+            r#"{"error":"authorization_pending","error_description":"waiting"}"#,
+        ) {
+            ClientError::AuthorizationError(error) => assert_eq!("authorization_pending", error),
+            e => panic!("unexpected error {:?}", e),
+        }
+
+        match PhysnaHttpClient::oauth_error(
+            StatusCode::UNAUTHORIZED,
+            r#"{"error":"invalid_client"}"#,
+        ) {
+            ClientError::UnexpectedResponse(status) => {
+                assert_eq!(StatusCode::UNAUTHORIZED, status)
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
 }
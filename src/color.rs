@@ -0,0 +1,115 @@
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Environment variable disabling colors, see https://no-color.org
+pub const ENV_NO_COLOR: &str = "NO_COLOR";
+
+#[derive(Debug, Error)]
+#[error("invalid color choice {0:?}")]
+pub struct InvalidColorChoice(String);
+
+/// When to color the terminal output, selected with `--color`
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    /// Color if the output is a terminal and NO_COLOR is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn names() -> Vec<&'static str> {
+        vec!["auto", "always", "never"]
+    }
+
+    /// Returns true if output to a stream that is (or is not) a terminal should be colored
+    ///
+    /// An explicit choice on the command line takes precedence over NO_COLOR.
+    pub fn enabled(&self, terminal: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                terminal && std::env::var_os(ENV_NO_COLOR).is_none_or(|value| value.is_empty())
+            }
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = InvalidColorChoice;
+
+    fn from_str(choice: &str) -> Result<ColorChoice, InvalidColorChoice> {
+        match choice.to_lowercase().as_str() {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(InvalidColorChoice(choice.to_string())),
+        }
+    }
+}
+
+/// Terminal styles used by the presentation of the output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    Bold,
+    Red,
+}
+
+impl Style {
+    fn code(&self) -> &'static str {
+        match self {
+            Style::Bold => "1",
+            Style::Red => "31",
+        }
+    }
+
+    /// Wraps the text in the ANSI escape sequences of the style
+    pub fn paint(&self, text: &str) -> String {
+        format!("\x1b[{}m{}\x1b[0m", self.code(), text)
+    }
+}
+
+/// Highlights the header row of output in the table or grid format
+pub fn paint_table_header(table: &str, borders: bool) -> String {
+    // a grid starts with its top border
+    let header = match borders {
+        true => 1,
+        false => 0,
+    };
+    table
+        .lines()
+        .enumerate()
+        .map(|(index, line)| match index == header {
+            true => Style::Bold.paint(line),
+            false => line.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_choice() {
+        assert_eq!(ColorChoice::Never, ColorChoice::from_str("NEVER").unwrap());
+        assert!(ColorChoice::from_str("sometimes").is_err());
+
+        assert!(ColorChoice::Always.enabled(false));
+        assert!(!ColorChoice::Never.enabled(true));
+        assert!(!ColorChoice::Auto.enabled(false));
+    }
+
+    #[test]
+    fn test_paint() {
+        assert_eq!("\x1b[31mfailed\x1b[0m", Style::Red.paint("failed"));
+
+        assert_eq!(
+            "\x1b[1mID  NAME\x1b[0m\n--  ----",
+            paint_table_header("ID  NAME\n--  ----", false)
+        );
+    }
+}
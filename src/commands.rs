@@ -1,3 +1,6 @@
+use crate::arguments::parse_duration;
+use crate::color::ColorChoice;
+use crate::configuration::CredentialStoreConfiguration;
 use crate::format::OutputFormat;
 use clap::{Arg, ArgMatches, Command};
 use std::path::PathBuf;
@@ -5,6 +8,7 @@ use url::Url;
 
 pub const COMMAND_CONFIG: &str = "config";
 pub const COMMAND_EXPORT: &str = "export";
+pub const COMMAND_IMPORT: &str = "import";
 pub const COMMAND_SHOW: &str = "show";
 pub const COMMAND_PATH: &str = "path";
 pub const COMMAND_SET: &str = "set";
@@ -13,9 +17,21 @@ pub const COMMAND_TENANT: &str = "tenant";
 pub const COMMAND_FOLDERS: &str = "folders";
 pub const COMMAND_LOGIN: &str = "login";
 pub const COMMAND_LOGOFF: &str = "logoff";
+pub const COMMAND_LOGOUT: &str = "logout";
+pub const COMMAND_AUTH: &str = "auth";
+pub const COMMAND_STATUS: &str = "status";
+pub const COMMAND_WHOAMI: &str = "whoami";
+pub const COMMAND_TOKEN: &str = "token";
+pub const COMMAND_REFRESH: &str = "refresh";
+pub const COMMAND_CHECK: &str = "check";
+pub const COMMAND_CREDENTIAL_STORE: &str = "credential-store";
+pub const COMMAND_NETWORK: &str = "network";
+pub const COMMAND_INIT: &str = "init";
 
 pub const PARAMETER_FORMAT: &str = "format";
 pub const PARAMETER_OUTPUT: &str = "output";
+pub const PARAMETER_INPUT: &str = "input";
+pub const PARAMETER_INCLUDE_SECRETS: &str = "include-secrets";
 pub const PARAMETER_API_URL: &str = "api_url";
 pub const PARAMETER_OIDC_URL: &str = "oidc_url";
 pub const PARAMETER_CLIENT_ID: &str = "client_id";
@@ -23,26 +39,84 @@ pub const PARAMETER_CLIENT_SECRET: &str = "client_secret";
 pub const PARAMETER_ID: &str = "id";
 pub const PARAMETER_TENANT: &str = "tenant";
 pub const PARAMETER_TENANT_ALIAS: &str = "alias";
+pub const PARAMETER_STRICT_MODELS: &str = "strict-models";
+pub const PARAMETER_PROFILE: &str = "profile";
+pub const PARAMETER_CONFIG: &str = "config";
+pub const PARAMETER_DEFAULT: &str = "default";
+pub const PARAMETER_REFRESH_WINDOW: &str = "refresh-window";
+pub const PARAMETER_DEVICE: &str = "device";
+pub const PARAMETER_TYPE: &str = "type";
+pub const PARAMETER_PATH: &str = "path";
+pub const PARAMETER_KEY_FILE: &str = "key-file";
+pub const PARAMETER_FALLBACK: &str = "fallback";
+pub const PARAMETER_PROXY_URL: &str = "proxy-url";
+pub const PARAMETER_PROXY_USERNAME: &str = "proxy-username";
+pub const PARAMETER_NO_PROXY: &str = "no-proxy";
+pub const PARAMETER_CA_FILE: &str = "ca-file";
+pub const PARAMETER_COLUMNS_MAP: &str = "columns-map";
+pub const PARAMETER_ALL: &str = "all";
+pub const PARAMETER_WIPE: &str = "wipe";
+pub const PARAMETER_CLIENT_CERT: &str = "client-cert";
+pub const PARAMETER_CLIENT_KEY: &str = "client-key";
+pub const PARAMETER_REQUIRE: &str = "require";
+pub const PARAMETER_FIELDS: &str = "fields";
+pub const PARAMETER_FILTER: &str = "filter";
+pub const PARAMETER_TEMPLATE: &str = "template";
+pub const PARAMETER_TEMPLATE_FILE: &str = "template-file";
+pub const PARAMETER_APPEND: &str = "append";
+pub const PARAMETER_COLOR: &str = "color";
+pub const PARAMETER_ERROR_FORMAT: &str = "error-format";
+pub const PARAMETER_NO_INPUT: &str = "no-input";
+
+/// Environment variable that disables all prompts, like --no-input
+pub const ENV_NO_INPUT: &str = "PCLI2_NO_INPUT";
 
 pub fn create_cli_commands() -> ArgMatches {
+    create_cli().get_matches()
+}
+
+/// Returns the definition of the command line
+pub fn create_cli() -> Command {
     let format_parameter = Arg::new(PARAMETER_FORMAT)
         .short('f')
         .long(PARAMETER_FORMAT)
         .num_args(1)
         .required(false)
-        .default_value("json")
         .global(true)
-        .help("Output data format")
+        .help("Output data format (defaults to the configured output format, or a table in a terminal)")
         .value_parser(OutputFormat::names());
 
+    // shared by all commands, the export commands require it
     let output_file_parameter = Arg::new(PARAMETER_OUTPUT)
         .short('o')
         .long(PARAMETER_OUTPUT)
         .num_args(1)
+        .global(true)
+        .help("writes the output to this file instead of the standard output")
+        .value_parser(clap::value_parser!(PathBuf));
+
+    let append_parameter = Arg::new(PARAMETER_APPEND)
+        .long(PARAMETER_APPEND)
+        .num_args(0)
+        .global(true)
+        .action(clap::ArgAction::SetTrue)
+        .requires(PARAMETER_OUTPUT)
+        .help("appends to the output file instead of replacing it");
+
+    let input_file_parameter = Arg::new(PARAMETER_INPUT)
+        .short('i')
+        .long(PARAMETER_INPUT)
+        .num_args(1)
         .required(true)
-        .help("output file path")
+        .help("input file path")
         .value_parser(clap::value_parser!(PathBuf));
 
+    let include_secrets_parameter = Arg::new(PARAMETER_INCLUDE_SECRETS)
+        .long(PARAMETER_INCLUDE_SECRETS)
+        .num_args(0)
+        .action(clap::ArgAction::SetTrue)
+        .help("include the client secrets, encrypted with a passphrase");
+
     let id_parameter = Arg::new(PARAMETER_ID)
         .short('i')
         .long(PARAMETER_ID)
@@ -61,7 +135,30 @@ pub fn create_cli_commands() -> ArgMatches {
         .short('t')
         .long(PARAMETER_TENANT)
         .num_args(1)
-        .required(true);
+        .required(false)
+        .global(true)
+        .help("tenant alias or ID (defaults to the default tenant of the profile)");
+
+    let default_tenant_parameter = Arg::new(PARAMETER_DEFAULT)
+        .long(PARAMETER_DEFAULT)
+        .num_args(0)
+        .action(clap::ArgAction::SetTrue)
+        .help("make this the default tenant of the profile");
+
+    let profile_parameter = Arg::new(PARAMETER_PROFILE)
+        .short('p')
+        .long(PARAMETER_PROFILE)
+        .num_args(1)
+        .global(true)
+        .help("configuration profile, e.g. for a different environment");
+
+    let config_parameter = Arg::new(PARAMETER_CONFIG)
+        .long(PARAMETER_CONFIG)
+        .num_args(1)
+        .global(true)
+        .value_name("FILE")
+        .value_parser(clap::value_parser!(PathBuf))
+        .help("configuration file to use instead of the default one");
 
     let api_url_parameter = Arg::new(PARAMETER_API_URL)
         .long(PARAMETER_API_URL)
@@ -86,8 +183,103 @@ pub fn create_cli_commands() -> ArgMatches {
     let client_secret_parameter = Arg::new(PARAMETER_CLIENT_SECRET)
         .long(PARAMETER_CLIENT_SECRET)
         .num_args(1)
-        .required(true)
-        .help("OpenID Connect client secret");
+        .required(false)
+        .help("OpenID Connect client secret, not needed to login with --device");
+
+    let strict_models_parameter = Arg::new(PARAMETER_STRICT_MODELS)
+        .long(PARAMETER_STRICT_MODELS)
+        .num_args(0)
+        .global(true)
+        .action(clap::ArgAction::SetTrue)
+        .help("fail on API responses containing properties unknown to the model");
+
+    let refresh_window_parameter = Arg::new(PARAMETER_REFRESH_WINDOW)
+        .long(PARAMETER_REFRESH_WINDOW)
+        .num_args(1)
+        .global(true)
+        .value_name("DURATION")
+        .default_value("60s")
+        .value_parser(parse_duration)
+        .help("refresh stored tokens that expire within this duration, e.g. 90s or 5m");
+
+    let client_cert_parameter = Arg::new(PARAMETER_CLIENT_CERT)
+        .long(PARAMETER_CLIENT_CERT)
+        .num_args(1)
+        .global(true)
+        .value_name("FILE")
+        .requires(PARAMETER_CLIENT_KEY)
+        .value_parser(clap::value_parser!(PathBuf))
+        .help("PEM client certificate for gateways requiring mutual TLS");
+
+    let client_key_parameter = Arg::new(PARAMETER_CLIENT_KEY)
+        .long(PARAMETER_CLIENT_KEY)
+        .num_args(1)
+        .global(true)
+        .value_name("FILE")
+        .requires(PARAMETER_CLIENT_CERT)
+        .value_parser(clap::value_parser!(PathBuf))
+        .help("PEM (PKCS#8) private key of the client certificate");
+
+    let columns_map_parameter = Arg::new(PARAMETER_COLUMNS_MAP)
+        .long(PARAMETER_COLUMNS_MAP)
+        .num_args(1)
+        .global(true)
+        .value_name("FILE")
+        .value_parser(clap::value_parser!(PathBuf))
+        .help("YAML file renaming and reordering the columns of CSV and table output");
+
+    let fields_parameter = Arg::new(PARAMETER_FIELDS)
+        .long(PARAMETER_FIELDS)
+        .num_args(1)
+        .global(true)
+        .value_delimiter(',')
+        .conflicts_with(PARAMETER_COLUMNS_MAP)
+        .help("comma separated list of the columns to include in CSV and table output");
+
+    let template_parameter = Arg::new(PARAMETER_TEMPLATE)
+        .long(PARAMETER_TEMPLATE)
+        .num_args(1)
+        .global(true)
+        .help("template rendering each output record, e.g. '{{NAME}} -> {{ID}}', implies --format template");
+
+    let template_file_parameter = Arg::new(PARAMETER_TEMPLATE_FILE)
+        .long(PARAMETER_TEMPLATE_FILE)
+        .num_args(1)
+        .global(true)
+        .value_name("FILE")
+        .value_parser(clap::value_parser!(PathBuf))
+        .conflicts_with(PARAMETER_TEMPLATE)
+        .help("file containing the template rendering each output record");
+
+    let color_parameter = Arg::new(PARAMETER_COLOR)
+        .long(PARAMETER_COLOR)
+        .num_args(1)
+        .global(true)
+        .default_value("auto")
+        .value_parser(ColorChoice::names())
+        .help("colors the output in a terminal, unless NO_COLOR is set");
+
+    let error_format_parameter = Arg::new(PARAMETER_ERROR_FORMAT)
+        .long(PARAMETER_ERROR_FORMAT)
+        .num_args(1)
+        .global(true)
+        .default_value("text")
+        .value_parser(["text", "json"])
+        .help("format of error messages, json writes {code, message, context} objects to the standard error (invalid command line arguments are still reported as text)");
+
+    let no_input_parameter = Arg::new(PARAMETER_NO_INPUT)
+        .long(PARAMETER_NO_INPUT)
+        .num_args(0)
+        .global(true)
+        .action(clap::ArgAction::SetTrue)
+        .help("never prompt for input, fail with the option or variable to supply instead (also set by PCLI2_NO_INPUT=1)");
+
+    let filter_parameter = Arg::new(PARAMETER_FILTER)
+        .long(PARAMETER_FILTER)
+        .num_args(1)
+        .action(clap::ArgAction::Append)
+        .value_name("EXPRESSION")
+        .help("only lists items matching the expression, e.g. name~=bracket, can be repeated");
 
     Command::new(env!("CARGO_PKG_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
@@ -96,6 +288,27 @@ pub fn create_cli_commands() -> ArgMatches {
         .propagate_version(true)
         .subcommand_required(true)
         .arg_required_else_help(true)
+        .arg(strict_models_parameter)
+        .arg(refresh_window_parameter)
+        .arg(profile_parameter)
+        .arg(tenant_parameter)
+        .arg(config_parameter)
+        .arg(columns_map_parameter)
+        .arg(fields_parameter)
+        .arg(template_parameter)
+        .arg(template_file_parameter)
+        .arg(output_file_parameter)
+        .arg(append_parameter)
+        .arg(color_parameter)
+        .arg(error_format_parameter)
+        .arg(no_input_parameter)
+        .arg(client_cert_parameter)
+        .arg(client_key_parameter)
+        .subcommand(
+            // First run
+            Command::new(COMMAND_INIT)
+                .about("interactively configures a tenant, the default tenant and the output format"),
+        )
         .subcommand(
             // Configuration
             Command::new(COMMAND_CONFIG)
@@ -115,8 +328,13 @@ pub fn create_cli_commands() -> ArgMatches {
                 )
                 .subcommand(
                     Command::new(COMMAND_EXPORT)
-                        .about("exports the current configuration as a Yaml file")
-                        .arg(output_file_parameter),
+                        .about("exports the current configuration as a Yaml file to the --output file")
+                        .arg(include_secrets_parameter),
+                )
+                .subcommand(
+                    Command::new(COMMAND_IMPORT)
+                        .about("imports tenants from a previously exported Yaml file")
+                        .arg(input_file_parameter.clone()),
                 )
                 .subcommand(
                     Command::new(COMMAND_SET)
@@ -130,7 +348,78 @@ pub fn create_cli_commands() -> ArgMatches {
                                 .arg(api_url_parameter)
                                 .arg(oidc_url_parameter)
                                 .arg(client_id_parameter)
-                                .arg(client_secret_parameter),
+                                .arg(client_secret_parameter)
+                                .arg(default_tenant_parameter),
+                        )
+                        .subcommand(
+                            Command::new(COMMAND_CREDENTIAL_STORE)
+                                .about("selects where client secrets and tokens are stored")
+                                .arg(
+                                    Arg::new(PARAMETER_TYPE)
+                                        .long(PARAMETER_TYPE)
+                                        .num_args(1)
+                                        .required(true)
+                                        .help("credential store backend")
+                                        .value_parser(CredentialStoreConfiguration::names()),
+                                )
+                                .arg(
+                                    Arg::new(PARAMETER_PATH)
+                                        .long(PARAMETER_PATH)
+                                        .num_args(1)
+                                        .required(false)
+                                        .help("location of the encrypted credential file")
+                                        .value_parser(clap::value_parser!(PathBuf)),
+                                )
+                                .arg(
+                                    Arg::new(PARAMETER_KEY_FILE)
+                                        .long(PARAMETER_KEY_FILE)
+                                        .num_args(1)
+                                        .required(false)
+                                        .help("file containing the passphrase of the encrypted credential file")
+                                        .value_parser(clap::value_parser!(PathBuf)),
+                                )
+                                .arg(
+                                    Arg::new(PARAMETER_FALLBACK)
+                                        .long(PARAMETER_FALLBACK)
+                                        .num_args(1)
+                                        .required(false)
+                                        .help("backend used when the keyring is not available, only with --type keyring, the path and key file apply to it")
+                                        .value_parser(["encrypted-file", "environment"]),
+                                ),
+                        )
+                        .subcommand(
+                            Command::new(COMMAND_NETWORK)
+                                .about("sets the proxy, CA and client certificates, omitted options are cleared")
+                                .arg(
+                                    Arg::new(PARAMETER_PROXY_URL)
+                                        .long(PARAMETER_PROXY_URL)
+                                        .num_args(1)
+                                        .required(false)
+                                        .help("HTTP(S) proxy URL")
+                                        .value_parser(clap::value_parser!(Url)),
+                                )
+                                .arg(
+                                    Arg::new(PARAMETER_PROXY_USERNAME)
+                                        .long(PARAMETER_PROXY_USERNAME)
+                                        .num_args(1)
+                                        .required(false)
+                                        .help("proxy user name, the password is read from PCLI2_PROXY_PASSWORD"),
+                                )
+                                .arg(
+                                    Arg::new(PARAMETER_NO_PROXY)
+                                        .long(PARAMETER_NO_PROXY)
+                                        .num_args(1)
+                                        .required(false)
+                                        .help("comma separated hosts reached without the proxy"),
+                                )
+                                .arg(
+                                    Arg::new(PARAMETER_CA_FILE)
+                                        .long(PARAMETER_CA_FILE)
+                                        .num_args(1)
+                                        .required(false)
+                                        .help("PEM file with additional trusted CA certificates")
+                                        .value_parser(clap::value_parser!(PathBuf)),
+                                ),
                         ),
                 )
                 .subcommand(
@@ -145,20 +434,94 @@ pub fn create_cli_commands() -> ArgMatches {
             // Folders
             Command::new(COMMAND_FOLDERS)
                 .about("lists all folders")
-                .arg(tenant_parameter.clone())
-                .arg(format_parameter),
+                .arg(format_parameter.clone())
+                .arg(filter_parameter),
         )
         .subcommand(
             // Login
             Command::new(COMMAND_LOGIN)
-                .about("attempts to login for this tenant")
-                .arg(tenant_parameter.clone()),
+                .about("attempts to login for this tenant"),
         )
         .subcommand(
             // Logoff
             Command::new(COMMAND_LOGOFF)
-                .about("attempts to logoff for this tenant")
-                .arg(tenant_parameter.clone()),
+                .about("attempts to logoff for this tenant"),
+        )
+        .subcommand(
+            // Authentication
+            Command::new(COMMAND_AUTH)
+                .about("working with authentication")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new(COMMAND_LOGIN)
+                        .about("attempts to login for this tenant")
+                        .arg(
+                            Arg::new(PARAMETER_DEVICE)
+                                .long(PARAMETER_DEVICE)
+                                .num_args(0)
+                                .action(clap::ArgAction::SetTrue)
+                                .help("login interactively in a browser with a device code"),
+                        ),
+                )
+                .subcommand(
+                    Command::new(COMMAND_LOGOUT)
+                        .about("removes the stored tokens for this tenant")
+                        .arg(
+                            Arg::new(PARAMETER_ALL)
+                                .long(PARAMETER_ALL)
+                                .num_args(0)
+                                .action(clap::ArgAction::SetTrue)
+                                .conflicts_with(PARAMETER_TENANT)
+                                .help("logout from all tenants of all profiles"),
+                        )
+                        .arg(
+                            Arg::new(PARAMETER_WIPE)
+                                .long(PARAMETER_WIPE)
+                                .num_args(0)
+                                .action(clap::ArgAction::SetTrue)
+                                .help("also remove the client secrets and the tenant configurations"),
+                        ),
+                )
+                .subcommand(
+                    Command::new(COMMAND_STATUS)
+                        .about("shows the state of the stored token for this tenant")
+                        .arg(format_parameter.clone()),
+                )
+                .subcommand(
+                    Command::new(COMMAND_CHECK)
+                        .about("fails unless the token of this tenant was granted all of the required scopes")
+                        .arg(
+                            Arg::new(PARAMETER_REQUIRE)
+                                .long(PARAMETER_REQUIRE)
+                                .num_args(1)
+                                .required(true)
+                                .value_delimiter(',')
+                                .help("comma separated list of required scopes"),
+                        ),
+                )
+                .subcommand(
+                    Command::new(COMMAND_EXPORT)
+                        .about("exports all profiles and client secrets into the --output file encrypted with a passphrase"),
+                )
+                .subcommand(
+                    Command::new(COMMAND_IMPORT)
+                        .about("imports profiles and client secrets from an encrypted export")
+                        .arg(input_file_parameter),
+                )
+                .subcommand(
+                    Command::new(COMMAND_TOKEN)
+                        .about("working with the access token")
+                        .subcommand_required(true)
+                        .subcommand(
+                            Command::new(COMMAND_REFRESH)
+                                .about("obtains and stores a new token with the stored credentials")
+                                .arg(format_parameter.clone()),
+                        ),
+                )
+                .subcommand(
+                    Command::new(COMMAND_WHOAMI)
+                        .about("shows the user logged in for this tenant")
+                        .arg(format_parameter),
+                ),
         )
-        .get_matches()
 }
@@ -1,23 +1,31 @@
+use crate::files::{write_atomically, FileLock};
 use crate::format::{
-    CsvRecordProducer, FormattingError, JsonProducer, OutputFormat, OutputFormatter,
+    format_item, to_ndjson, CsvRecordProducer, FormattingError, JsonProducer, OutputFormat,
+    OutputFormatter, YamlProducer,
+};
+use crate::security::{
+    decrypt_with_passphrase, encrypt_with_passphrase, CredentialStore, EncryptedFileStore,
+    EnvironmentStore, FallbackStore, Keyring, KeyringError, ENV_CLIENT_SECRET, SECRET_KEY,
 };
-use crate::security::{Keyring, KeyringError, SECRET_KEY};
-use csv::Writer;
 use dirs::config_dir;
-use log::trace;
+use log::{trace, warn};
 use serde::{Deserialize, Serialize};
 use serde_json;
 use serde_yaml;
 use std::{
     collections::HashMap,
     fs::{self, File},
-    io::{BufWriter, Write},
-    path::PathBuf,
+    io::Write,
+    path::{Path, PathBuf},
 };
 use url::Url;
 
 pub const DEFAULT_APPLICATION_ID: &'static str = "pcli2";
 pub const DEFAULT_CONFIGURATION_FILE_NAME: &'static str = "config.yml";
+/// Name of the file used by the encrypted file credential store, unless configured otherwise
+pub const DEFAULT_CREDENTIAL_FILE_NAME: &str = "credentials.enc";
+/// Written to exported configuration files in place of secrets that were not included
+pub const SECRET_PLACEHOLDER: &str = "<not exported>";
 
 #[derive(Debug, thiserror::Error)]
 pub enum ConfigurationError {
@@ -31,6 +39,8 @@ pub enum ConfigurationError {
     MissingRequiredPropertyValue { name: String },
     #[error("unknown tenant \"{tenant_id:?}\"")]
     UnknownTenant { tenant_id: String },
+    #[error("tenant ID \"{tenant_id}\" is configured under several aliases ({aliases}), please use one of them")]
+    AmbiguousTenant { tenant_id: String, aliases: String },
     #[error("credentials not provided")]
     CredentialsNotProvided,
     #[error("{cause:?}")]
@@ -40,14 +50,178 @@ pub enum ConfigurationError {
     },
     #[error("security error {0}")]
     KeyringError(#[from] KeyringError),
+    #[error("failed to encrypt the secret for tenant \"{alias}\"")]
+    FailedToEncryptSecret { alias: String },
+    #[error("failed to decrypt the secret for tenant \"{alias}\", the passphrase may be wrong")]
+    FailedToDecryptSecret { alias: String },
+    #[error("encrypted secrets require a passphrase to import")]
+    PassphraseRequired,
+    #[error("failed to encrypt the credential export")]
+    FailedToEncryptExport,
+    #[error("failed to decrypt the credential export, the passphrase may be wrong")]
+    FailedToDecryptExport,
+    #[error("the configuration was modified by another process (expected revision {expected}, found {found}), please retry")]
+    ConcurrentModification { expected: u64, found: u64 },
 }
 
+/// Backend keeping the client secrets and tokens, selected with the `credential_store` option
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum CredentialStoreConfiguration {
+    /// The keyring (secret service) of the operating system
+    Keyring {
+        /// Store used instead when the keyring is locked or there is no secret service
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        fallback: Option<Box<CredentialStoreConfiguration>>,
+    },
+    /// A file encrypted with a passphrase, for machines without a secret service
+    EncryptedFile {
+        /// Location of the file (defaults to the configuration directory)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<PathBuf>,
+        /// File containing the passphrase (defaults to the `PCLI2_PASSPHRASE` environment variable)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        key_file: Option<PathBuf>,
+    },
+    /// Nothing is stored, the credentials are only read from the environment variables
+    Environment,
+}
+
+impl Default for CredentialStoreConfiguration {
+    fn default() -> CredentialStoreConfiguration {
+        CredentialStoreConfiguration::Keyring { fallback: None }
+    }
+}
+
+impl CredentialStoreConfiguration {
+    /// Names of the supported backends, as used in the configuration file
+    pub fn names() -> Vec<&'static str> {
+        vec!["keyring", "encrypted-file", "environment"]
+    }
+
+    /// Creates the credential store of this backend
+    pub fn store(&self) -> Box<dyn CredentialStore> {
+        match self {
+            CredentialStoreConfiguration::Keyring { fallback } => match fallback {
+                Some(fallback) => Box::new(FallbackStore::new(
+                    Box::new(Keyring::default()),
+                    fallback.store(),
+                )),
+                None => Box::new(Keyring::default()),
+            },
+            CredentialStoreConfiguration::EncryptedFile { path, key_file } => {
+                let path = match path {
+                    Some(path) => path.clone(),
+                    None => {
+                        let mut path = config_dir().unwrap_or_default();
+                        path.push(DEFAULT_APPLICATION_ID);
+                        path.push(DEFAULT_CREDENTIAL_FILE_NAME);
+                        path
+                    }
+                };
+                Box::new(EncryptedFileStore::new(path, key_file.clone()))
+            }
+            CredentialStoreConfiguration::Environment => Box::new(EnvironmentStore {}),
+        }
+    }
+}
+
+/// Settings for reaching the API through a corporate network
+///
+/// Each setting can be overridden with an environment variable (see `client::ENV_PROXY_URL` and the like).
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkConfiguration {
+    /// Proxy for all HTTP and HTTPS requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    proxy_url: Option<Url>,
+    /// User name for proxies requiring authentication, the password is only read from the environment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    proxy_username: Option<String>,
+    /// Comma separated hosts that are reached without the proxy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    no_proxy: Option<String>,
+    /// PEM file with additional CA certificates, e.g. of a TLS intercepting proxy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ca_file: Option<PathBuf>,
+    /// PEM file with the client certificate for gateways requiring mutual TLS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    client_cert: Option<PathBuf>,
+    /// PEM file with the PKCS#8 private key of the client certificate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    client_key: Option<PathBuf>,
+}
+
+impl NetworkConfiguration {
+    pub fn new(
+        proxy_url: Option<Url>,
+        proxy_username: Option<String>,
+        no_proxy: Option<String>,
+        ca_file: Option<PathBuf>,
+    ) -> NetworkConfiguration {
+        NetworkConfiguration {
+            proxy_url,
+            proxy_username,
+            no_proxy,
+            ca_file,
+            client_cert: None,
+            client_key: None,
+        }
+    }
+
+    /// Sets the client certificate and its private key presented for mutual TLS
+    pub fn set_client_identity(
+        &mut self,
+        client_cert: Option<PathBuf>,
+        client_key: Option<PathBuf>,
+    ) {
+        self.client_cert = client_cert;
+        self.client_key = client_key;
+    }
+
+    pub fn proxy_url(&self) -> Option<Url> {
+        self.proxy_url.clone()
+    }
+
+    pub fn proxy_username(&self) -> Option<String> {
+        self.proxy_username.clone()
+    }
+
+    pub fn no_proxy(&self) -> Option<String> {
+        self.no_proxy.clone()
+    }
+
+    pub fn ca_file(&self) -> Option<PathBuf> {
+        self.ca_file.clone()
+    }
+
+    pub fn client_cert(&self) -> Option<PathBuf> {
+        self.client_cert.clone()
+    }
+
+    pub fn client_key(&self) -> Option<PathBuf> {
+        self.client_key.clone()
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == NetworkConfiguration::default()
+    }
+}
+
+/// Number of attempts to apply a change to the default configuration when other processes modify it concurrently
+const MAX_UPDATE_ATTEMPTS: usize = 5;
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TenantConfiguration {
     tenant_id: String,
     api_url: Url,
     oidc_url: Url,
     client_id: String,
+    /// Name of the profile this tenant belongs to (None for the default profile)
+    #[serde(skip)]
+    profile: Option<String>,
+    /// Where the credentials of this tenant are kept, as selected by the configuration
+    #[serde(skip)]
+    credential_store: CredentialStoreConfiguration,
 }
 
 impl TenantConfiguration {
@@ -62,6 +236,8 @@ impl TenantConfiguration {
             api_url,
             oidc_url,
             client_id,
+            profile: None,
+            credential_store: CredentialStoreConfiguration::default(),
         }
     }
 
@@ -105,14 +281,46 @@ impl TenantConfiguration {
         self.client_id.clone()
     }
 
+    pub fn set_profile(&mut self, profile: Option<String>) {
+        self.profile = profile;
+    }
+
+    pub fn profile(&self) -> Option<String> {
+        self.profile.clone()
+    }
+
+    pub fn set_credential_store(&mut self, credential_store: CredentialStoreConfiguration) {
+        self.credential_store = credential_store;
+    }
+
+    /// Returns the store keeping the credentials of this tenant
+    pub fn credential_store(&self) -> Box<dyn CredentialStore> {
+        self.credential_store.store()
+    }
+
+    /// Returns the identifier under which the credentials of this tenant are stored in the keyring
+    ///
+    /// Tenants of named profiles are kept apart, so that the same tenant ID can be configured
+    /// with different credentials in different environments.
+    pub fn keyring_id(&self) -> String {
+        match &self.profile {
+            Some(profile) => format!("{}/{}", profile, self.tenant_id),
+            None => self.tenant_id.clone(),
+        }
+    }
+
     #[allow(dead_code)]
     pub fn set_client_secret(&mut self, client_secret: String) -> Result<(), ConfigurationError> {
-        Keyring::default().put(&self.tenant_id, String::from(SECRET_KEY), client_secret)?;
+        self.credential_store()
+            .put(&self.keyring_id(), String::from(SECRET_KEY), client_secret)?;
         Ok(())
     }
 
     pub fn client_secret(&self) -> Result<String, ConfigurationError> {
-        match Keyring::default().get(&self.tenant_id, String::from(SECRET_KEY))? {
+        match self
+            .credential_store()
+            .get(&self.keyring_id(), String::from(SECRET_KEY))?
+        {
             Some(secret) => Ok(secret),
             None => Err(ConfigurationError::CredentialsNotProvided),
         }
@@ -141,14 +349,13 @@ impl CsvRecordProducer for TenantConfiguration {
 
 impl JsonProducer for TenantConfiguration {}
 
+impl YamlProducer for TenantConfiguration {}
+
 impl OutputFormatter for TenantConfiguration {
     type Item = TenantConfiguration;
 
     fn format(&self, format: OutputFormat) -> Result<String, FormattingError> {
-        match format {
-            OutputFormat::Json => Ok(self.to_json()?),
-            OutputFormat::Csv => Ok(self.to_csv_with_header()?),
-        }
+        format_item(self, format)
     }
 }
 
@@ -158,6 +365,8 @@ pub struct TenantConfigurationBuilder {
     oidc_url: Option<Url>,
     client_id: Option<String>,
     client_secret: Option<String>,
+    profile: Option<String>,
+    credential_store: CredentialStoreConfiguration,
 }
 
 impl TenantConfigurationBuilder {
@@ -168,9 +377,24 @@ impl TenantConfigurationBuilder {
             oidc_url: None,
             client_id: None,
             client_secret: None,
+            profile: None,
+            credential_store: CredentialStoreConfiguration::default(),
         }
     }
 
+    pub fn profile(&mut self, profile: Option<String>) -> &mut TenantConfigurationBuilder {
+        self.profile = profile;
+        self
+    }
+
+    pub fn credential_store(
+        &mut self,
+        credential_store: CredentialStoreConfiguration,
+    ) -> &mut TenantConfigurationBuilder {
+        self.credential_store = credential_store;
+        self
+    }
+
     pub fn tenant_id(&mut self, id: String) -> &mut TenantConfigurationBuilder {
         self.tenant_id = Some(id.clone());
         self
@@ -225,33 +449,86 @@ impl TenantConfigurationBuilder {
             }),
         }?;
 
-        let client_secret = match &self.client_secret {
-            Some(client_secret) => Ok(client_secret.clone()),
-            None => Err(ConfigurationError::MissingRequiredPropertyValue {
-                name: "client_secret".to_string(),
-            }),
-        }?;
-
         let mut tenant_config = TenantConfiguration::new(tenant_id, api_url, oidc_url, client_id);
-        tenant_config.set_client_secret(client_secret)?;
+        tenant_config.set_profile(self.profile.clone());
+        tenant_config.set_credential_store(self.credential_store.clone());
+        // users logging in with the device flow have no client secret
+        match &self.client_secret {
+            Some(_) if tenant_config.credential_store().is_read_only() => warn!(
+                "The client secret of tenant \"{}\" is not stored, it is read from {} instead",
+                tenant_config.tenant_id(),
+                ENV_CLIENT_SECRET
+            ),
+            Some(client_secret) => tenant_config.set_client_secret(client_secret.clone())?,
+            None => (),
+        }
 
         Ok(tenant_config)
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub struct Configuration {
+/// Named set of tenants, e.g. for one of several environments (development, staging, production)
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    #[serde(default)]
     tenants: HashMap<String, TenantConfiguration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default_tenant: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Configuration {
+    /// The profile used when no profile is selected
+    #[serde(flatten)]
+    default_profile: Profile,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    profiles: HashMap<String, Profile>,
+    /// Backend keeping the credentials of the tenants of all profiles
+    #[serde(default)]
+    credential_store: CredentialStoreConfiguration,
+    /// Proxy and TLS settings shared by all tenants
+    #[serde(default, skip_serializing_if = "NetworkConfiguration::is_empty")]
+    network: NetworkConfiguration,
+    /// Output format used when none is given on the command line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output_format: Option<OutputFormat>,
+    /// Incremented on every save to the default location, used to detect concurrent modifications
+    #[serde(default)]
+    revision: u64,
+    /// The profile all tenant operations apply to (None for the default profile)
+    #[serde(skip)]
+    profile: Option<String>,
+    /// The file this configuration is saved to (None for the default location)
+    #[serde(skip)]
+    path: Option<PathBuf>,
 }
 
 impl Default for Configuration {
     fn default() -> Self {
         Self {
-            tenants: HashMap::new(),
+            default_profile: Profile::default(),
+            profiles: HashMap::new(),
+            credential_store: CredentialStoreConfiguration::default(),
+            network: NetworkConfiguration::default(),
+            output_format: None,
+            revision: 0,
+            profile: None,
+            path: None,
         }
     }
 }
 
+/// Two configurations are equal if they have the same content, regardless of their revision
+impl PartialEq for Configuration {
+    fn eq(&self, other: &Self) -> bool {
+        self.default_profile == other.default_profile
+            && self.profiles == other.profiles
+            && self.credential_store == other.credential_store
+            && self.network == other.network
+            && self.output_format == other.output_format
+    }
+}
+
 impl CsvRecordProducer for Configuration {
     fn csv_header() -> Vec<String> {
         TenantConfiguration::csv_header()
@@ -260,7 +537,7 @@ impl CsvRecordProducer for Configuration {
     fn as_csv_records(&self) -> Vec<Vec<String>> {
         let mut records: Vec<Vec<String>> = Vec::new();
 
-        for (_, tenant) in &self.tenants {
+        for (_, tenant) in self.tenants() {
             records.push(tenant.as_csv_records()[0].clone());
         }
 
@@ -274,34 +551,265 @@ impl OutputFormatter for Configuration {
     fn format(&self, format: OutputFormat) -> Result<String, FormattingError> {
         match format {
             OutputFormat::Json => {
-                let json = serde_json::to_string_pretty(self);
+                let profile = self.active_profile().cloned().unwrap_or_default();
+                let json = serde_json::to_string_pretty(&profile);
                 match json {
                     Ok(json) => Ok(json),
                     Err(e) => Err(FormattingError::FormatFailure { cause: Box::new(e) }),
                 }
             }
-            OutputFormat::Csv => {
-                let buf = BufWriter::new(Vec::new());
-                let mut wtr = Writer::from_writer(buf);
-                wtr.write_record(&TenantConfiguration::csv_header())
-                    .unwrap();
-                for record in self.as_csv_records() {
-                    wtr.write_record(&record).unwrap();
-                }
-                match wtr.flush() {
-                    Ok(_) => {
-                        let bytes = wtr.into_inner().unwrap().into_inner().unwrap();
-                        let csv = String::from_utf8(bytes).unwrap();
-                        Ok(csv.clone())
-                    }
+            OutputFormat::Yaml => {
+                let profile = self.active_profile().cloned().unwrap_or_default();
+                let yaml = serde_yaml::to_string(&profile);
+                match yaml {
+                    Ok(yaml) => Ok(yaml),
                     Err(e) => Err(FormattingError::FormatFailure { cause: Box::new(e) }),
                 }
             }
+            OutputFormat::Ndjson => {
+                let profile = self.active_profile().cloned().unwrap_or_default();
+                to_ndjson([&profile])
+            }
+            OutputFormat::Table => Ok(self.to_table(false)?),
+            OutputFormat::Grid => Ok(self.to_table(true)?),
+            OutputFormat::Csv | OutputFormat::Template => Ok(self.to_csv_with_header()?),
+        }
+    }
+}
+
+/// Portable form of the configuration produced by `config export`
+///
+/// Secrets are kept in the keyring and are never written in clear text. Unless explicitly requested,
+/// they are replaced by a placeholder. Otherwise, they are encrypted with a passphrase.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConfigurationExport {
+    #[serde(flatten)]
+    configuration: Configuration,
+    #[serde(default)]
+    secrets: HashMap<String, String>,
+}
+
+impl ConfigurationExport {
+    pub fn load(path: &PathBuf) -> Result<ConfigurationExport, ConfigurationError> {
+        match fs::read_to_string(path) {
+            Ok(content) => match serde_yaml::from_str(&content) {
+                Ok(export) => Ok(export),
+                Err(cause) => Err(ConfigurationError::FailedToLoadData {
+                    cause: Box::new(cause),
+                }),
+            },
+            Err(cause) => Err(ConfigurationError::FailedToLoadData {
+                cause: Box::new(cause),
+            }),
+        }
+    }
+
+    /// Loads an export produced by [`Configuration::export_encrypted`]
+    pub fn load_encrypted(
+        path: &PathBuf,
+        passphrase: &str,
+    ) -> Result<ConfigurationExport, ConfigurationError> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(cause) => {
+                return Err(ConfigurationError::FailedToLoadData {
+                    cause: Box::new(cause),
+                })
+            }
+        };
+        let content = match decrypt_with_passphrase(&content, passphrase) {
+            Ok(content) => content,
+            Err(_) => return Err(ConfigurationError::FailedToDecryptExport),
+        };
+        match serde_yaml::from_str(&content) {
+            Ok(export) => Ok(export),
+            Err(cause) => Err(ConfigurationError::FailedToLoadData {
+                cause: Box::new(cause),
+            }),
         }
     }
+
+    /// Returns true if the export contains encrypted secrets, which require a passphrase to import
+    pub fn has_secrets(&self) -> bool {
+        self.secrets
+            .values()
+            .any(|secret| secret != SECRET_PLACEHOLDER)
+    }
 }
 
 impl Configuration {
+    /// Exports the configuration into a file that can be imported on another machine
+    ///
+    /// If a passphrase is provided, the client secrets stored in the keyring are included encrypted with it.
+    pub fn export(
+        &self,
+        path: &PathBuf,
+        passphrase: Option<&str>,
+    ) -> Result<(), ConfigurationError> {
+        let export = self.to_export(passphrase)?;
+
+        Self::create_parent_directory(path)?;
+        match File::create(path) {
+            Ok(file) => match serde_yaml::to_writer(file, &export) {
+                Ok(()) => Ok(()),
+                Err(e) => Err(ConfigurationError::FailedToWriteData { cause: Box::new(e) }),
+            },
+            Err(e) => Err(ConfigurationError::FailedToWriteData { cause: Box::new(e) }),
+        }
+    }
+
+    /// Exports the configuration with all client secrets into a file that is encrypted as a whole
+    ///
+    /// This is meant for provisioning machines, as not even the tenant names can be read without the passphrase.
+    pub fn export_encrypted(
+        &self,
+        path: &PathBuf,
+        passphrase: &str,
+    ) -> Result<(), ConfigurationError> {
+        let export = match serde_yaml::to_string(&self.to_export(Some(passphrase))?) {
+            Ok(export) => export,
+            Err(e) => return Err(ConfigurationError::FailedToWriteData { cause: Box::new(e) }),
+        };
+        let export = match encrypt_with_passphrase(&export, passphrase) {
+            Ok(export) => export,
+            Err(_) => return Err(ConfigurationError::FailedToEncryptExport),
+        };
+
+        Self::create_parent_directory(path)?;
+        match fs::write(path, export) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(ConfigurationError::FailedToWriteData { cause: Box::new(e) }),
+        }
+    }
+
+    fn to_export(
+        &self,
+        passphrase: Option<&str>,
+    ) -> Result<ConfigurationExport, ConfigurationError> {
+        let mut secrets: HashMap<String, String> = HashMap::new();
+        for (profile, alias, tenant) in self.all_tenants() {
+            let alias = Self::qualified_alias(profile.as_ref(), &alias);
+            let secret = match passphrase {
+                Some(passphrase) => match tenant.client_secret() {
+                    Ok(secret) => match encrypt_with_passphrase(&secret, passphrase) {
+                        Ok(secret) => secret,
+                        Err(_) => {
+                            return Err(ConfigurationError::FailedToEncryptSecret {
+                                alias: alias.clone(),
+                            })
+                        }
+                    },
+                    Err(e) => {
+                        warn!("Secret for tenant \"{}\" not exported: {}", alias, e);
+                        SECRET_PLACEHOLDER.to_string()
+                    }
+                },
+                None => SECRET_PLACEHOLDER.to_string(),
+            };
+            secrets.insert(alias.clone(), secret);
+        }
+
+        Ok(ConfigurationExport {
+            configuration: self.clone(),
+            secrets,
+        })
+    }
+
+    /// Merges the tenants of an exported configuration into this one
+    ///
+    /// Encrypted secrets are decrypted with the passphrase and stored back into the keyring.
+    /// Tenants with the same alias are replaced.
+    pub fn import(
+        &mut self,
+        export: &ConfigurationExport,
+        passphrase: Option<&str>,
+    ) -> Result<(), ConfigurationError> {
+        // all secrets are decrypted first, so that a wrong passphrase leaves nothing behind
+        let mut tenants = Vec::new();
+        for (profile, alias, tenant) in export.configuration.all_tenants() {
+            let qualified_alias = Self::qualified_alias(profile.as_ref(), &alias);
+            let secret = match export.secrets.get(&qualified_alias) {
+                Some(secret) if secret != SECRET_PLACEHOLDER => {
+                    let passphrase = match passphrase {
+                        Some(passphrase) => passphrase,
+                        None => return Err(ConfigurationError::PassphraseRequired),
+                    };
+                    match decrypt_with_passphrase(secret, passphrase) {
+                        Ok(secret) => Some(secret),
+                        Err(_) => {
+                            return Err(ConfigurationError::FailedToDecryptSecret {
+                                alias: qualified_alias,
+                            })
+                        }
+                    }
+                }
+                _ => {
+                    warn!(
+                        "No secret imported for tenant \"{}\". Set it again with \"config set tenant\".",
+                        qualified_alias
+                    );
+                    None
+                }
+            };
+            tenants.push((profile, alias, tenant, secret));
+        }
+
+        // the secrets stored so far are put back if storing one of them fails
+        let mut stored: Vec<(TenantConfiguration, Option<String>)> = Vec::new();
+        for (_, alias, tenant, secret) in tenants.iter_mut() {
+            let secret = match secret {
+                Some(secret) => secret.clone(),
+                None => continue,
+            };
+            tenant.set_credential_store(self.credential_store.clone());
+            if tenant.credential_store().is_read_only() {
+                warn!(
+                    "The secret of tenant \"{}\" is not stored, it is read from {} instead",
+                    alias, ENV_CLIENT_SECRET
+                );
+                continue;
+            }
+            let previous = tenant.client_secret().ok();
+            if let Err(e) = tenant.set_client_secret(secret) {
+                Self::restore_secrets(&stored);
+                return Err(e);
+            }
+            stored.push((tenant.clone(), previous));
+        }
+
+        for (profile, alias, mut tenant, _) in tenants {
+            tenant.set_profile(None);
+            tenant.set_credential_store(CredentialStoreConfiguration::default());
+            self.profile_mut(profile.as_ref())
+                .tenants
+                .insert(alias, tenant);
+        }
+
+        Ok(())
+    }
+
+    /// Puts back the secrets that the tenants had before an import, removing those that did not have one
+    fn restore_secrets(stored: &[(TenantConfiguration, Option<String>)]) {
+        for (tenant, previous) in stored.iter().rev() {
+            let store = tenant.credential_store();
+            let result = match previous {
+                Some(previous) => store.put(
+                    &tenant.keyring_id(),
+                    String::from(SECRET_KEY),
+                    previous.clone(),
+                ),
+                None => store.delete(&tenant.keyring_id(), String::from(SECRET_KEY)),
+            };
+            if let Err(e) = result {
+                warn!(
+                    "Failed to restore the secret of tenant \"{}\": {}",
+                    tenant.keyring_id(),
+                    e
+                );
+            }
+        }
+    }
+
     pub fn get_default_configuration_file_path() -> Result<PathBuf, ConfigurationError> {
         let configuration_directory = config_dir();
         match configuration_directory {
@@ -316,6 +824,26 @@ impl Configuration {
         }
     }
 
+    /// Loads the configuration from the file, or starts a new one saved there if it does not exist yet
+    ///
+    /// All subsequent saves go to this file instead of the default location.
+    pub fn load_from(path: &Path) -> Result<Configuration, ConfigurationError> {
+        let mut configuration = match path.exists() {
+            true => Configuration::load_from_file(path.to_path_buf())?,
+            false => Configuration::default(),
+        };
+        configuration.path = Some(path.to_path_buf());
+        Ok(configuration)
+    }
+
+    /// Returns the file this configuration is saved to
+    pub fn file_path(&self) -> Result<PathBuf, ConfigurationError> {
+        match &self.path {
+            Some(path) => Ok(path.clone()),
+            None => Self::get_default_configuration_file_path(),
+        }
+    }
+
     pub fn load_default() -> Result<Configuration, ConfigurationError> {
         let default_file_path = Configuration::get_default_configuration_file_path()?;
         Configuration::load_from_file(default_file_path)
@@ -345,7 +873,7 @@ impl Configuration {
         }
     }
 
-    pub fn save(&self, path: &PathBuf) -> Result<(), ConfigurationError> {
+    fn create_parent_directory(path: &Path) -> Result<(), ConfigurationError> {
         // first check if the parent directory exists and try to create it if not
         let configuration_directory = path.parent();
         match configuration_directory {
@@ -359,23 +887,233 @@ impl Configuration {
             None => return Err(ConfigurationError::FailedToFindConfigurationDirectory),
         }
 
-        let file = File::create(&path);
-        match file {
-            Ok(file) => {
-                let writer: Box<dyn Write> = Box::new(file);
-                Ok(self.write(writer)?)
-            }
+        Ok(())
+    }
+
+    /// Writes the configuration to the file, replacing it atomically
+    pub fn save(&self, path: &Path) -> Result<(), ConfigurationError> {
+        Self::create_parent_directory(path)?;
+
+        let content = match serde_yaml::to_string(self) {
+            Ok(content) => content,
+            Err(e) => return Err(ConfigurationError::FailedToWriteData { cause: Box::new(e) }),
+        };
+        match write_atomically(path, content.as_bytes()) {
+            Ok(()) => Ok(()),
             Err(e) => Err(ConfigurationError::FailedToWriteData { cause: Box::new(e) }),
         }
     }
 
-    pub fn save_to_default(&self) -> Result<(), ConfigurationError> {
-        self.save(&Self::get_default_configuration_file_path()?)
+    /// Returns the revision of the configuration
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Saves the configuration to its file, which is the default location unless loaded with [`Configuration::load_from`]
+    ///
+    /// The save fails with `ConfigurationError::ConcurrentModification` if the file was changed by another
+    /// process since this configuration was loaded, instead of silently overwriting those changes. The
+    /// revision is compared and the file written while holding its lock, so that no other save can slip in between.
+    pub fn save_to_default(&mut self) -> Result<(), ConfigurationError> {
+        let path = self.file_path()?;
+        Self::create_parent_directory(&path)?;
+        let _lock = match FileLock::acquire(&path) {
+            Ok(lock) => lock,
+            Err(e) => return Err(ConfigurationError::FailedToWriteData { cause: Box::new(e) }),
+        };
+        if path.exists() {
+            let current = Self::load_from_file(path.clone())?;
+            if current.revision != self.revision {
+                return Err(ConfigurationError::ConcurrentModification {
+                    expected: self.revision,
+                    found: current.revision,
+                });
+            }
+        }
+
+        let mut next = self.clone();
+        next.revision += 1;
+        next.save(&path)?;
+        self.revision = next.revision;
+        Ok(())
+    }
+
+    /// Applies a change to the latest saved configuration and saves it
+    ///
+    /// The configuration file is reloaded right before the change is applied to the selected profile.
+    /// If another process saves it in the meantime, the change is applied again to its newer content.
+    /// On success, this configuration is replaced by the saved one.
+    pub fn update_default<F>(&mut self, update: F) -> Result<(), ConfigurationError>
+    where
+        F: Fn(&mut Configuration) -> Result<(), ConfigurationError>,
+    {
+        let path = self.file_path()?;
+        let mut attempt = 1;
+        loop {
+            let mut configuration = Self::load_from(&path)?;
+            configuration.path = self.path.clone();
+            configuration.select_profile(self.profile.as_ref());
+            update(&mut configuration)?;
+
+            match configuration.save_to_default() {
+                Err(ConfigurationError::ConcurrentModification { .. })
+                    if attempt < MAX_UPDATE_ATTEMPTS =>
+                {
+                    trace!("Configuration modified concurrently, retrying...");
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+                Ok(()) => {
+                    *self = configuration;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Selects the profile that all subsequent tenant operations apply to
+    ///
+    /// None selects the default profile. A profile that does not exist yet is created once a tenant is added to it.
+    pub fn select_profile(&mut self, profile: Option<&String>) {
+        trace!("Selecting profile {:?}...", profile);
+        self.profile = profile.cloned();
+    }
+
+    /// Returns the name of the selected profile, or None if the default profile is selected
+    pub fn profile(&self) -> Option<String> {
+        self.profile.clone()
+    }
+
+    /// Returns the names of all named profiles
+    pub fn profile_names(&self) -> Vec<String> {
+        self.profiles.keys().map(|k| k.to_string()).collect()
+    }
+
+    fn active_profile(&self) -> Option<&Profile> {
+        match &self.profile {
+            Some(profile) => self.profiles.get(profile),
+            None => Some(&self.default_profile),
+        }
+    }
+
+    fn profile_mut(&mut self, profile: Option<&String>) -> &mut Profile {
+        match profile {
+            Some(profile) => self.profiles.entry(profile.clone()).or_default(),
+            None => &mut self.default_profile,
+        }
+    }
+
+    fn active_profile_mut(&mut self) -> &mut Profile {
+        let profile = self.profile.clone();
+        self.profile_mut(profile.as_ref())
+    }
+
+    fn tenants(&self) -> Vec<(String, TenantConfiguration)> {
+        match self.active_profile() {
+            Some(profile) => profile
+                .tenants
+                .iter()
+                .map(|(alias, tenant)| {
+                    let mut tenant = tenant.clone();
+                    tenant.set_profile(self.profile.clone());
+                    tenant.set_credential_store(self.credential_store.clone());
+                    (alias.clone(), tenant)
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the tenants of all profiles as (profile, alias, tenant)
+    fn all_tenants(&self) -> Vec<(Option<String>, String, TenantConfiguration)> {
+        let mut profiles: Vec<(Option<String>, &Profile)> = vec![(None, &self.default_profile)];
+        for (name, profile) in &self.profiles {
+            profiles.push((Some(name.clone()), profile));
+        }
+
+        let mut tenants = Vec::new();
+        for (name, profile) in profiles {
+            for (alias, tenant) in &profile.tenants {
+                let mut tenant = tenant.clone();
+                tenant.set_profile(name.clone());
+                tenant.set_credential_store(self.credential_store.clone());
+                tenants.push((name.clone(), alias.clone(), tenant));
+            }
+        }
+        tenants
+    }
+
+    /// Returns the configurations of the tenants of all profiles
+    pub fn all_tenant_configurations(&self) -> Vec<TenantConfiguration> {
+        self.all_tenants()
+            .into_iter()
+            .map(|(_, _, tenant)| tenant)
+            .collect()
+    }
+
+    /// Removes the tenants of all profiles, including the profiles themselves
+    pub fn delete_all_profiles(&mut self) {
+        self.default_profile = Profile::default();
+        self.profiles.clear();
+    }
+
+    /// Returns the alias prefixed with the name of its profile, unless it belongs to the default profile
+    fn qualified_alias(profile: Option<&String>, alias: &String) -> String {
+        match profile {
+            Some(profile) => format!("{}/{}", profile, alias),
+            None => alias.clone(),
+        }
+    }
+
+    /// Returns the backend keeping the credentials of all tenants
+    pub fn credential_store(&self) -> CredentialStoreConfiguration {
+        self.credential_store.clone()
+    }
+
+    /// Selects the backend keeping the credentials of all tenants
+    ///
+    /// Credentials already stored in the previous backend are not moved.
+    pub fn set_credential_store(&mut self, credential_store: CredentialStoreConfiguration) {
+        self.credential_store = credential_store;
+    }
+
+    /// Returns the proxy and TLS settings of all tenants
+    pub fn network(&self) -> NetworkConfiguration {
+        self.network.clone()
+    }
+
+    pub fn set_network(&mut self, network: NetworkConfiguration) {
+        self.network = network;
+    }
+
+    /// Returns the output format used when none is given on the command line, if one is configured
+    pub fn output_format(&self) -> Option<OutputFormat> {
+        self.output_format.clone()
+    }
+
+    pub fn set_output_format(&mut self, output_format: Option<OutputFormat>) {
+        self.output_format = output_format;
+    }
+
+    /// Sets the tenant used by the selected profile when none is specified
+    pub fn set_default_tenant(&mut self, tenant_alias: Option<&String>) {
+        self.active_profile_mut().default_tenant = tenant_alias.cloned();
+    }
+
+    /// Returns the alias of the tenant used by the selected profile when none is specified
+    pub fn default_tenant(&self) -> Option<String> {
+        match self.active_profile() {
+            Some(profile) => profile.default_tenant.clone(),
+            None => None,
+        }
     }
 
     #[allow(dead_code)]
     pub fn is_empty(&self) -> bool {
-        self.tenants.is_empty()
+        match self.active_profile() {
+            Some(profile) => profile.tenants.is_empty(),
+            None => true,
+        }
     }
 
     pub fn validate_tenant(
@@ -394,6 +1132,33 @@ impl Configuration {
         }
     }
 
+    /// Returns the alias of the tenant identified by its alias or by its tenant ID in the selected profile
+    ///
+    /// An alias takes precedence over a tenant ID.
+    pub fn resolve_tenant(&self, identifier: &String) -> Result<String, ConfigurationError> {
+        if self.tenant(identifier).is_some() {
+            return Ok(identifier.clone());
+        }
+
+        let mut aliases: Vec<String> = self
+            .tenants()
+            .into_iter()
+            .filter(|(_, tenant)| tenant.tenant_id == *identifier)
+            .map(|(alias, _)| alias)
+            .collect();
+        aliases.sort();
+        match aliases.len() {
+            0 => Err(ConfigurationError::UnknownTenant {
+                tenant_id: identifier.clone(),
+            }),
+            1 => Ok(aliases.remove(0)),
+            _ => Err(ConfigurationError::AmbiguousTenant {
+                tenant_id: identifier.clone(),
+                aliases: aliases.join(", "),
+            }),
+        }
+    }
+
     pub fn add_tenant(
         &mut self,
         tenant_alias: Option<&String>,
@@ -404,7 +1169,11 @@ impl Configuration {
             None => tenant.tenant_id.clone(),
         };
         trace!("Adding tenant {}...", alias);
-        self.tenants.insert(alias, tenant.clone());
+        // the profile is implied by where the tenant is stored
+        let mut tenant = tenant.clone();
+        tenant.set_profile(None);
+        tenant.set_credential_store(CredentialStoreConfiguration::default());
+        self.active_profile_mut().tenants.insert(alias, tenant);
 
         Ok(())
     }
@@ -412,27 +1181,41 @@ impl Configuration {
     /// Returns an Option of an owned instance of TenantConfiguration
     /// if one exists, or None
     pub fn tenant(&self, tenant_id: &String) -> Option<TenantConfiguration> {
-        let tenant = self.tenants.get(tenant_id);
+        let tenant = match self.active_profile() {
+            Some(profile) => profile.tenants.get(tenant_id),
+            None => None,
+        };
 
         match tenant {
-            Some(tenant) => Some(tenant.clone()),
+            Some(tenant) => {
+                let mut tenant = tenant.clone();
+                tenant.set_profile(self.profile.clone());
+                tenant.set_credential_store(self.credential_store.clone());
+                Some(tenant)
+            }
             None => None,
         }
     }
 
     pub fn delete_tenant(&mut self, tenant_id: &String) {
         trace!("Deleting tenant {}...", tenant_id);
-        self.tenants.remove(tenant_id);
+        let profile = self.active_profile_mut();
+        profile.tenants.remove(tenant_id);
+        if profile.default_tenant.as_ref() == Some(tenant_id) {
+            profile.default_tenant = None;
+        }
     }
 
     #[allow(dead_code)]
     pub fn delete_all_tenants(&mut self) {
-        self.tenants.clear()
+        let profile = self.active_profile_mut();
+        profile.tenants.clear();
+        profile.default_tenant = None;
     }
 
     #[allow(dead_code)]
     pub fn get_all_tenant_aliases(&self) -> Vec<String> {
-        self.tenants.keys().map(|k| k.to_string()).collect()
+        self.tenants().into_iter().map(|(alias, _)| alias).collect()
     }
 }
 
@@ -452,6 +1235,7 @@ mod tests {
     fn test_output_format_to_string() {
         assert_eq!(OutputFormat::Csv.to_string(), format::CSV);
         assert_eq!(OutputFormat::Json.to_string(), format::JSON);
+        assert_eq!(OutputFormat::Yaml.to_string(), format::YAML);
     }
 
     #[test]
@@ -464,6 +1248,10 @@ mod tests {
             OutputFormat::from_str(format::CSV).unwrap(),
             OutputFormat::Csv
         );
+        assert_eq!(
+            OutputFormat::from_str(format::YAML).unwrap(),
+            OutputFormat::Yaml
+        );
     }
 
     #[test]
@@ -478,7 +1266,14 @@ mod tests {
         assert_eq!(
             configuration,
             Configuration {
-                tenants: HashMap::new(),
+                default_profile: Profile::default(),
+                profiles: HashMap::new(),
+                credential_store: CredentialStoreConfiguration::default(),
+                network: NetworkConfiguration::default(),
+                output_format: None,
+                revision: 0,
+                profile: None,
+                path: None,
             }
         );
     }
@@ -490,7 +1285,7 @@ mod tests {
         let file = NamedTempFile::new().unwrap();
         let path = file.into_temp_path();
         let configuration = Configuration::default();
-        configuration.save(&path.to_path_buf()).unwrap();
+        configuration.save(&path).unwrap();
         path.close().unwrap();
     }
 
@@ -501,7 +1296,7 @@ mod tests {
         let file = NamedTempFile::new().unwrap();
         let path = file.into_temp_path();
         let configuration = Configuration::default();
-        configuration.save(&path.to_path_buf()).unwrap();
+        configuration.save(&path).unwrap();
 
         let configuration2 = Configuration::load_from_file(path.to_path_buf()).unwrap();
 
@@ -523,11 +1318,94 @@ mod tests {
 
     #[test]
     fn test_load_default_configuration() {
-        // make a copy of the original file
-        let new_configuration = Configuration::default();
+        // start from the current file, so that its revision matches
+        let mut new_configuration = Configuration::load_default().unwrap_or_default();
         new_configuration.save_to_default().unwrap();
-        let new_configuration = Configuration::load_default().unwrap();
-        assert_eq!(new_configuration, Configuration::default());
+        let loaded_configuration = Configuration::load_default().unwrap();
+        assert_eq!(loaded_configuration, new_configuration);
+        assert_eq!(
+            loaded_configuration.revision(),
+            new_configuration.revision()
+        );
+    }
+
+    #[test]
+    fn test_detect_concurrent_modification() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("pcli2.yml");
+        let mut configuration = Configuration::load_from(&path).unwrap();
+        let mut stale_configuration = configuration.clone();
+
+        configuration.save_to_default().unwrap();
+        match stale_configuration.save_to_default() {
+            Err(ConfigurationError::ConcurrentModification { expected, found }) => {
+                assert_eq!(expected + 1, found);
+            }
+            other => panic!("expected a concurrent modification error, got {:?}", other),
+        }
+
+        let revision = configuration.revision();
+        stale_configuration.update_default(|_| Ok(())).unwrap();
+        assert_eq!(revision + 1, stale_configuration.revision());
+    }
+
+    #[test]
+    fn test_configuration_file_override() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("pcli2.yml");
+
+        let mut configuration = Configuration::load_from(&path).unwrap();
+        assert!(configuration.is_empty());
+        assert_eq!(path, configuration.file_path().unwrap());
+
+        let tenant = TenantConfiguration::new(
+            "my_tenant".to_string(),
+            Url::parse("https://my_tenant.physna.com/api/v2").unwrap(),
+            Url::parse("https://authentication.com").unwrap(),
+            "my_client_id".to_string(),
+        );
+        configuration
+            .update_default(|configuration| {
+                configuration.add_tenant(Some(&"my_alias".to_string()), &tenant)
+            })
+            .unwrap();
+        assert_eq!(path, configuration.file_path().unwrap());
+
+        let loaded = Configuration::load_from(&path).unwrap();
+        assert_eq!(configuration, loaded);
+        assert_eq!(1, loaded.revision());
+    }
+
+    #[test]
+    fn test_concurrent_updates() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("pcli2.yml");
+
+        let threads: Vec<_> = (0..4)
+            .map(|index| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let tenant = TenantConfiguration::new(
+                        format!("tenant_{}", index),
+                        Url::parse("https://my_tenant.physna.com/api/v2").unwrap(),
+                        Url::parse("https://authentication.com").unwrap(),
+                        "my_client_id".to_string(),
+                    );
+                    let mut configuration = Configuration::load_from(&path).unwrap();
+                    configuration
+                        .update_default(|configuration| configuration.add_tenant(None, &tenant))
+                        .unwrap();
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // no update was lost
+        let loaded = Configuration::load_from(&path).unwrap();
+        assert_eq!(4, loaded.get_all_tenant_aliases().len());
+        assert_eq!(4, loaded.revision());
     }
 
     #[test]
@@ -562,6 +1440,8 @@ mod tests {
             api_url: api_url.clone(),
             oidc_url: oidc_url.clone(),
             client_id: client_id.clone(),
+            profile: None,
+            credential_store: CredentialStoreConfiguration::default(),
         };
 
         let tenant_config_two = TenantConfiguration::new(
@@ -586,6 +1466,8 @@ mod tests {
             api_url: api_url.clone(),
             oidc_url: oidc_url.clone(),
             client_id: client_id.clone(),
+            profile: None,
+            credential_store: CredentialStoreConfiguration::default(),
         };
         let secret = String::from("my super secret secret");
         tenant_config.set_client_secret(secret.to_owned()).unwrap();
@@ -601,7 +1483,7 @@ mod tests {
             Url::parse(format!("https://{}.physna.com/api/v2", tenant_id).as_str()).unwrap();
         let oidc_url = Url::parse("https://authentication.com").unwrap();
         let client_id = "my_client_id".to_string();
-        let json = r#"TenantConfiguration { tenant_id: "my_tenant", api_url: Url { scheme: "https", cannot_be_a_base: false, username: "", password: None, host: Some(Domain("my_tenant.physna.com")), port: None, path: "/api/v2", query: None, fragment: None }, oidc_url: Url { scheme: "https", cannot_be_a_base: false, username: "", password: None, host: Some(Domain("authentication.com")), port: None, path: "/", query: None, fragment: None }, client_id: "my_client_id" }"#;
+        let json = r#"TenantConfiguration { tenant_id: "my_tenant", api_url: Url { scheme: "https", cannot_be_a_base: false, username: "", password: None, host: Some(Domain("my_tenant.physna.com")), port: None, path: "/api/v2", query: None, fragment: None }, oidc_url: Url { scheme: "https", cannot_be_a_base: false, username: "", password: None, host: Some(Domain("authentication.com")), port: None, path: "/", query: None, fragment: None }, client_id: "my_client_id", profile: None, credential_store: Keyring { fallback: None } }"#;
 
         let tenant = TenantConfiguration::new(
             tenant_id.clone(),
@@ -669,6 +1551,38 @@ mod tests {
         assert_eq!(tenant2, None);
     }
 
+    #[test]
+    fn test_resolve_tenant() {
+        let mut configuration = Configuration::default();
+        let tenant = TenantConfiguration::new(
+            "my_tenant".to_string(),
+            Url::parse("https://my_tenant.physna.com/api/v2").unwrap(),
+            Url::parse("https://authentication.com").unwrap(),
+            "my_client_id".to_string(),
+        );
+        configuration
+            .add_tenant(Some(&"my_alias".to_string()), &tenant)
+            .unwrap();
+
+        let resolve = |identifier: &str| configuration.resolve_tenant(&identifier.to_string());
+        assert_eq!("my_alias", resolve("my_alias").unwrap());
+        assert_eq!("my_alias", resolve("my_tenant").unwrap());
+        assert!(matches!(
+            resolve("other_tenant"),
+            Err(ConfigurationError::UnknownTenant { .. })
+        ));
+
+        configuration
+            .add_tenant(Some(&"other_alias".to_string()), &tenant)
+            .unwrap();
+        match configuration.resolve_tenant(&"my_tenant".to_string()) {
+            Err(ConfigurationError::AmbiguousTenant { aliases, .. }) => {
+                assert_eq!("my_alias, other_alias", aliases)
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
     #[test]
     fn test_delete_tenant() {
         let mut configuration = Configuration::default();
@@ -745,6 +1659,36 @@ mod tests {
         assert!(configuration.is_empty());
     }
 
+    #[test]
+    fn test_delete_all_profiles() {
+        let mut configuration = Configuration::default();
+        let tenant = TenantConfiguration::new(
+            "my_tenant".to_string(),
+            Url::parse("https://my_tenant.physna.com/api/v2").unwrap(),
+            Url::parse("https://authentication.com").unwrap(),
+            "my_client_id".to_string(),
+        );
+        configuration
+            .add_tenant(Some(&"my_alias".to_string()), &tenant)
+            .unwrap();
+        configuration.select_profile(Some(&"staging".to_string()));
+        configuration
+            .add_tenant(Some(&"my_alias".to_string()), &tenant)
+            .unwrap();
+
+        let keyring_ids: Vec<String> = configuration
+            .all_tenant_configurations()
+            .iter()
+            .map(|tenant| tenant.keyring_id())
+            .collect();
+        assert_eq!(2, keyring_ids.len());
+        assert!(keyring_ids.contains(&"staging/my_tenant".to_string()));
+
+        configuration.delete_all_profiles();
+        assert!(configuration.all_tenant_configurations().is_empty());
+        assert!(configuration.profile_names().is_empty());
+    }
+
     #[test]
     fn test_tenant_ids() {
         let mut tenant_aliases = vec![
@@ -785,6 +1729,282 @@ mod tests {
         assert_eq!(produced_ids, tenant_aliases);
     }
 
+    #[test]
+    fn test_export_excludes_secrets() {
+        use tempfile::NamedTempFile;
+
+        let mut configuration = Configuration::default();
+        let tenant = TenantConfiguration::new(
+            "my_tenant".to_string(),
+            Url::parse("https://my_tenant.physna.com/api/v2").unwrap(),
+            Url::parse("https://authentication.com").unwrap(),
+            "my_client_id".to_string(),
+        );
+        configuration
+            .add_tenant(Some(&"my_alias".to_string()), &tenant)
+            .unwrap();
+
+        let file = NamedTempFile::new().unwrap();
+        let path = file.into_temp_path();
+        configuration.export(&path.to_path_buf(), None).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains(SECRET_PLACEHOLDER));
+
+        let export = ConfigurationExport::load(&path.to_path_buf()).unwrap();
+        assert!(!export.has_secrets());
+
+        let mut imported = Configuration::default();
+        imported.import(&export, None).unwrap();
+        assert_eq!(configuration, imported);
+    }
+
+    #[test]
+    fn test_encrypted_export() {
+        use tempfile::NamedTempFile;
+
+        let mut configuration = Configuration::default();
+        configuration.set_credential_store(CredentialStoreConfiguration::Environment);
+        let tenant = TenantConfiguration::new(
+            "my_tenant".to_string(),
+            Url::parse("https://my_tenant.physna.com/api/v2").unwrap(),
+            Url::parse("https://authentication.com").unwrap(),
+            "my_client_id".to_string(),
+        );
+        configuration
+            .add_tenant(Some(&"my_alias".to_string()), &tenant)
+            .unwrap();
+
+        let file = NamedTempFile::new().unwrap();
+        let temp_path = file.into_temp_path();
+        let path = temp_path.to_path_buf();
+        configuration.export_encrypted(&path, "passphrase").unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("my_tenant"));
+
+        let export = ConfigurationExport::load_encrypted(&path, "passphrase").unwrap();
+        assert_eq!(configuration, export.configuration);
+        assert!(matches!(
+            ConfigurationExport::load_encrypted(&path, "wrong passphrase"),
+            Err(ConfigurationError::FailedToDecryptExport)
+        ));
+    }
+
+    #[test]
+    fn test_failed_import_leaves_no_secrets() {
+        let directory = tempfile::tempdir().unwrap();
+        let key_file = directory.path().join("key");
+        fs::write(&key_file, "store passphrase").unwrap();
+        let credential_store = CredentialStoreConfiguration::EncryptedFile {
+            path: Some(directory.path().join("credentials.enc")),
+            key_file: Some(key_file),
+        };
+
+        let mut exported = Configuration::default();
+        for tenant_id in ["tenant_a", "tenant_b"] {
+            let tenant = TenantConfiguration::new(
+                tenant_id.to_string(),
+                Url::parse("https://my_tenant.physna.com/api/v2").unwrap(),
+                Url::parse("https://authentication.com").unwrap(),
+                "my_client_id".to_string(),
+            );
+            exported.add_tenant(None, &tenant).unwrap();
+        }
+        let mut secrets = HashMap::new();
+        secrets.insert(
+            "tenant_a".to_string(),
+            encrypt_with_passphrase("secret a", "passphrase").unwrap(),
+        );
+        secrets.insert(
+            "tenant_b".to_string(),
+            encrypt_with_passphrase("secret b", "other passphrase").unwrap(),
+        );
+        let export = ConfigurationExport {
+            configuration: exported,
+            secrets,
+        };
+
+        let mut configuration = Configuration::default();
+        configuration.set_credential_store(credential_store.clone());
+        assert!(matches!(
+            configuration.import(&export, Some("passphrase")),
+            Err(ConfigurationError::FailedToDecryptSecret { .. })
+        ));
+        assert!(configuration.get_all_tenant_aliases().is_empty());
+        assert_eq!(
+            None,
+            credential_store
+                .store()
+                .get("tenant_a", SECRET_KEY.to_string())
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_import_plain_configuration_file() {
+        use tempfile::NamedTempFile;
+
+        let file = NamedTempFile::new().unwrap();
+        let path = file.into_temp_path();
+        Configuration::default().save(&path).unwrap();
+
+        let export = ConfigurationExport::load(&path.to_path_buf()).unwrap();
+        assert!(!export.has_secrets());
+    }
+
+    #[test]
+    fn test_profiles() {
+        let mut configuration = Configuration::default();
+        let alias = "my_alias".to_string();
+        let profile = "staging".to_string();
+
+        let tenant = TenantConfiguration::new(
+            "my_tenant".to_string(),
+            Url::parse("https://my_tenant.physna.com/api/v2").unwrap(),
+            Url::parse("https://authentication.com").unwrap(),
+            "my_client_id".to_string(),
+        );
+        configuration.add_tenant(Some(&alias), &tenant).unwrap();
+
+        let staging_tenant = TenantConfiguration::new(
+            "my_tenant".to_string(),
+            Url::parse("https://my_tenant.staging.physna.com/api/v2").unwrap(),
+            Url::parse("https://authentication.com").unwrap(),
+            "my_staging_client_id".to_string(),
+        );
+        configuration.select_profile(Some(&profile));
+        assert!(configuration.is_empty());
+        configuration
+            .add_tenant(Some(&alias), &staging_tenant)
+            .unwrap();
+        configuration.set_default_tenant(Some(&alias));
+
+        let found = configuration.tenant(&alias).unwrap();
+        assert_eq!(staging_tenant.api_url(), found.api_url());
+        assert_eq!(Some(profile.clone()), found.profile());
+        assert_eq!("staging/my_tenant", found.keyring_id());
+        assert_eq!(Some(alias.clone()), configuration.default_tenant());
+        assert_eq!(vec![profile.clone()], configuration.profile_names());
+
+        configuration.select_profile(None);
+        let found = configuration.tenant(&alias).unwrap();
+        assert_eq!(tenant, found);
+        assert_eq!("my_tenant", found.keyring_id());
+        assert_eq!(None, configuration.default_tenant());
+
+        // profiles survive a round trip through the configuration file
+        let yaml = serde_yaml::to_string(&configuration).unwrap();
+        let mut loaded: Configuration = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(configuration, loaded);
+        loaded.select_profile(Some(&profile));
+        assert_eq!(Some(alias), loaded.default_tenant());
+    }
+
+    #[test]
+    fn test_credential_store_configuration() {
+        let yaml = "credential_store:\n  type: encrypted-file\n  key_file: /etc/pcli2/key\n";
+        let mut configuration: Configuration = serde_yaml::from_str(yaml).unwrap();
+        let credential_store = CredentialStoreConfiguration::EncryptedFile {
+            path: None,
+            key_file: Some(PathBuf::from("/etc/pcli2/key")),
+        };
+        assert_eq!(credential_store, configuration.credential_store());
+
+        let tenant = TenantConfiguration::new(
+            "my_tenant".to_string(),
+            Url::parse("https://my_tenant.physna.com/api/v2").unwrap(),
+            Url::parse("https://authentication.com").unwrap(),
+            "my_client_id".to_string(),
+        );
+        configuration
+            .add_tenant(Some(&"my_alias".to_string()), &tenant)
+            .unwrap();
+        let found = configuration.tenant(&"my_alias".to_string()).unwrap();
+        assert_eq!(credential_store, found.credential_store);
+
+        let configuration: Configuration = serde_yaml::from_str("{}").unwrap();
+        assert_eq!(
+            CredentialStoreConfiguration::default(),
+            configuration.credential_store()
+        );
+    }
+
+    #[test]
+    fn test_configured_output_format() {
+        let mut configuration = Configuration::default();
+        assert_eq!(None, configuration.output_format());
+        assert!(!serde_yaml::to_string(&configuration)
+            .unwrap()
+            .contains("output_format"));
+
+        configuration.set_output_format(Some(OutputFormat::Csv));
+        let yaml = serde_yaml::to_string(&configuration).unwrap();
+        let loaded: Configuration = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(Some(OutputFormat::Csv), loaded.output_format());
+    }
+
+    #[test]
+    fn test_credential_store_fallback() {
+        let yaml = "credential_store:\n  type: keyring\n  fallback:\n    type: encrypted-file\n    key_file: /etc/pcli2/key\n";
+        let configuration: Configuration = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            CredentialStoreConfiguration::Keyring {
+                fallback: Some(Box::new(CredentialStoreConfiguration::EncryptedFile {
+                    path: None,
+                    key_file: Some(PathBuf::from("/etc/pcli2/key")),
+                })),
+            },
+            configuration.credential_store()
+        );
+
+        // a plain keyring is written without the fallback
+        let yaml = serde_yaml::to_string(&CredentialStoreConfiguration::default()).unwrap();
+        assert_eq!("type: keyring\n", yaml);
+    }
+
+    #[test]
+    fn test_build_with_environment_store() {
+        let tenant = TenantConfiguration::builder()
+            .tenant_id("my_tenant".to_string())
+            .api_url(Url::parse("https://my_tenant.physna.com/api/v2").unwrap())
+            .oidc_url(Url::parse("https://authentication.com").unwrap())
+            .client_id("my_client_id".to_string())
+            .client_secret("my_secret".to_string())
+            .credential_store(CredentialStoreConfiguration::Environment)
+            .build()
+            .unwrap();
+        assert_eq!(
+            CredentialStoreConfiguration::Environment,
+            tenant.credential_store
+        );
+    }
+
+    #[test]
+    fn test_network_configuration() {
+        let yaml = "network:\n  proxy_url: http://proxy.corp:8080/\n  ca_file: /etc/ssl/corp.pem\n";
+        let configuration: Configuration = serde_yaml::from_str(yaml).unwrap();
+        let network = configuration.network();
+        assert_eq!(
+            Some(Url::parse("http://proxy.corp:8080").unwrap()),
+            network.proxy_url()
+        );
+        assert_eq!(None, network.proxy_username());
+        assert_eq!(Some(PathBuf::from("/etc/ssl/corp.pem")), network.ca_file());
+        assert_eq!(None, network.client_cert());
+
+        let mut network = network;
+        network.set_client_identity(
+            Some(PathBuf::from("/etc/pcli2/client.pem")),
+            Some(PathBuf::from("/etc/pcli2/client.key")),
+        );
+        let yaml = serde_yaml::to_string(&network).unwrap();
+        let loaded: NetworkConfiguration = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(network, loaded);
+
+        // the section is left out while there is nothing configured
+        let yaml = serde_yaml::to_string(&Configuration::default()).unwrap();
+        assert!(!yaml.contains("network"));
+    }
+
     #[test]
     fn test_configuration_tenant_setters() {
         let wrong = "wrong_value".to_string();
@@ -0,0 +1,148 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Distinguishes the temporary files of concurrent writes within the same process
+static TEMPORARY_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the path of a file next to the given one, with the prefix and suffix added to its name
+fn sibling_path(path: &Path, prefix: &str, suffix: &str) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!("{}{}{}", prefix, name, suffix))
+}
+
+/// Exclusive lock on a file, held until dropped
+///
+/// The lock is taken on a separate `.lock` file next to the protected one, so that the protected file
+/// can be replaced while the lock is held. The operating system releases the lock when the process
+/// exits, therefore an abandoned lock never has to be removed.
+pub struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    /// Waits until no other process holds the lock of the file and takes it
+    pub fn acquire(path: &Path) -> io::Result<FileLock> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(sibling_path(path, "", ".lock"))?;
+        file.lock()?;
+        Ok(FileLock { _file: file })
+    }
+}
+
+/// Replaces the content of the file atomically
+///
+/// The content is written to a temporary file in the same directory, which is then renamed over the
+/// target, so that readers never see a partially written file. An existing file keeps its
+/// permissions, while a new one gets the default permissions of the user.
+pub fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    write_temporary_and_rename(path, content, false)
+}
+
+/// Replaces the content of the file atomically, like [`write_atomically`], making it readable by its owner only
+///
+/// On Unix, the temporary file is created with mode 0600, so the content is never readable by others.
+pub fn write_private_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    write_temporary_and_rename(path, content, true)
+}
+
+fn write_temporary_and_rename(path: &Path, content: &[u8], private: bool) -> io::Result<()> {
+    let temporary_path = sibling_path(
+        path,
+        ".",
+        &format!(
+            ".{}.{}.tmp",
+            std::process::id(),
+            TEMPORARY_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ),
+    );
+
+    let result = (|| {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        if private {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&temporary_path)?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        if !private {
+            if let Ok(metadata) = fs::metadata(path) {
+                fs::set_permissions(&temporary_path, metadata.permissions())?;
+            }
+        }
+        fs::rename(&temporary_path, path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary_path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_atomically() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("config.yml");
+
+        write_atomically(&path, b"first").unwrap();
+        assert_eq!("first", fs::read_to_string(&path).unwrap());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+            write_atomically(&path, b"second").unwrap();
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(0o600, mode & 0o777);
+        }
+
+        write_atomically(&path, b"third").unwrap();
+        assert_eq!("third", fs::read_to_string(&path).unwrap());
+
+        // no temporary files are left behind
+        assert_eq!(1, fs::read_dir(directory.path()).unwrap().count());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_private_atomically() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("credentials.enc");
+        fs::write(&path, "first").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_private_atomically(&path, b"second").unwrap();
+        assert_eq!("second", fs::read_to_string(&path).unwrap());
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o777);
+    }
+
+    #[test]
+    fn test_file_lock() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("config.yml");
+
+        let lock = FileLock::acquire(&path).unwrap();
+        assert!(directory.path().join("config.yml.lock").exists());
+        drop(lock);
+
+        // the lock can be taken again once released
+        let _lock = FileLock::acquire(&path).unwrap();
+    }
+}
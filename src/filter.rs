@@ -0,0 +1,192 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum FilterError {
+    #[error("invalid filter expression {0:?}, expected <field><operator><value> with one of the operators =, !=, ~=, >, >=, <, <=")]
+    InvalidExpression(String),
+}
+
+/// Comparison of a [`Condition`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Equal,
+    NotEqual,
+    /// Case insensitive substring match
+    Contains,
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+impl Operator {
+    /// Operators in the order they are looked for, so that e.g. ">=" is not mistaken for ">"
+    const SYMBOLS: [(&'static str, Operator); 7] = [
+        ("!=", Operator::NotEqual),
+        ("~=", Operator::Contains),
+        (">=", Operator::GreaterOrEqual),
+        ("<=", Operator::LessOrEqual),
+        ("=", Operator::Equal),
+        (">", Operator::Greater),
+        ("<", Operator::Less),
+    ];
+}
+
+/// Single comparison of a field with a value, e.g. `name~=bracket`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    field: String,
+    operator: Operator,
+    value: String,
+}
+
+impl FromStr for Condition {
+    type Err = FilterError;
+
+    fn from_str(expression: &str) -> Result<Condition, FilterError> {
+        let invalid = || FilterError::InvalidExpression(expression.to_string());
+
+        let (position, symbol, operator) = Operator::SYMBOLS
+            .iter()
+            .filter_map(|(symbol, operator)| {
+                expression
+                    .find(symbol)
+                    .map(|position| (position, *symbol, *operator))
+            })
+            .min_by_key(|(position, symbol, _)| (*position, std::cmp::Reverse(symbol.len())))
+            .ok_or_else(invalid)?;
+
+        let field = expression[..position].trim();
+        if field.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Condition {
+            field: field.to_string(),
+            operator,
+            value: expression[position + symbol.len()..].trim().to_string(),
+        })
+    }
+}
+
+impl Condition {
+    /// Compares numbers numerically and anything else as text
+    ///
+    /// Dates in ISO 8601 format, as returned by the API, therefore compare chronologically.
+    fn compare(actual: &str, expected: &str) -> Ordering {
+        match (actual.parse::<f64>(), expected.parse::<f64>()) {
+            (Ok(actual), Ok(expected)) => actual.partial_cmp(&expected).unwrap_or(Ordering::Equal),
+            _ => actual.cmp(expected),
+        }
+    }
+
+    /// Returns true if the field of the object satisfies the condition
+    ///
+    /// A missing field only satisfies the `!=` operator.
+    pub fn matches(&self, object: &Value) -> bool {
+        let actual = match object.get(&self.field) {
+            None | Some(Value::Null) => return self.operator == Operator::NotEqual,
+            Some(Value::String(actual)) => actual.clone(),
+            Some(actual) => actual.to_string(),
+        };
+
+        match self.operator {
+            Operator::Equal => Self::compare(&actual, &self.value) == Ordering::Equal,
+            Operator::NotEqual => Self::compare(&actual, &self.value) != Ordering::Equal,
+            Operator::Contains => actual.to_lowercase().contains(&self.value.to_lowercase()),
+            Operator::Greater => Self::compare(&actual, &self.value) == Ordering::Greater,
+            Operator::GreaterOrEqual => Self::compare(&actual, &self.value) != Ordering::Less,
+            Operator::Less => Self::compare(&actual, &self.value) == Ordering::Less,
+            Operator::LessOrEqual => Self::compare(&actual, &self.value) != Ordering::Greater,
+        }
+    }
+}
+
+/// Client side filter of listings, matching the items that satisfy all of its conditions
+///
+/// The conditions apply to the fields of the items as they appear in the JSON output.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Filter {
+    conditions: Vec<Condition>,
+}
+
+impl Filter {
+    pub fn parse<S: AsRef<str>>(expressions: &[S]) -> Result<Filter, FilterError> {
+        let conditions = expressions
+            .iter()
+            .map(|expression| Condition::from_str(expression.as_ref()))
+            .collect::<Result<Vec<Condition>, FilterError>>()?;
+        Ok(Filter { conditions })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.conditions.is_empty()
+    }
+
+    pub fn matches<T: Serialize>(&self, item: &T) -> bool {
+        if self.conditions.is_empty() {
+            return true;
+        }
+
+        match serde_json::to_value(item) {
+            Ok(object) => self
+                .conditions
+                .iter()
+                .all(|condition| condition.matches(&object)),
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_condition() {
+        assert_eq!(
+            Condition {
+                field: "name".to_string(),
+                operator: Operator::Contains,
+                value: "bracket".to_string(),
+            },
+            Condition::from_str("name~=bracket").unwrap()
+        );
+        assert_eq!(
+            Operator::GreaterOrEqual,
+            Condition::from_str("created >= 2024-01-01")
+                .unwrap()
+                .operator
+        );
+        assert_eq!("a=b", Condition::from_str("name=a=b").unwrap().value);
+
+        assert!(Condition::from_str("name").is_err());
+        assert!(Condition::from_str("=value").is_err());
+    }
+
+    #[test]
+    fn test_filter_matches() {
+        let folder = json!({
+            "id": 120,
+            "name": "Bracket Assembly",
+            "state": "finished",
+            "createdAt": "2024-03-05T10:00:00Z"
+        });
+
+        let filter = |expressions: &[&str]| Filter::parse(expressions).unwrap();
+        assert!(filter(&[]).matches(&folder));
+        assert!(filter(&["name~=bracket", "state=finished"]).matches(&folder));
+        assert!(!filter(&["name~=bracket", "state=processing"]).matches(&folder));
+        assert!(filter(&["createdAt>2024-01-01"]).matches(&folder));
+        assert!(!filter(&["createdAt<2024-01-01"]).matches(&folder));
+        assert!(filter(&["id>=100", "id<1000"]).matches(&folder));
+        assert!(filter(&["id>99.5"]).matches(&folder));
+        assert!(filter(&["owner!=me"]).matches(&folder));
+        assert!(!filter(&["owner=me"]).matches(&folder));
+    }
+}
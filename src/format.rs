@@ -1,12 +1,19 @@
 use csv::Writer;
 use serde::{Deserialize, Serialize};
 use serde_json;
+use std::collections::HashMap;
 use std::io::BufWriter;
+use std::path::Path;
 use std::str::FromStr;
 use strum::EnumIter;
 
 pub const JSON: &'static str = "json";
 pub const CSV: &'static str = "csv";
+pub const YAML: &str = "yaml";
+pub const NDJSON: &str = "ndjson";
+pub const TABLE: &str = "table";
+pub const GRID: &str = "grid";
+pub const TEMPLATE: &str = "template";
 pub const TREE: &'static str = "tree";
 
 #[derive(Debug, thiserror::Error)]
@@ -15,6 +22,14 @@ pub enum FormattingError {
     UnsupportedOutputFormat { format: String },
     #[error("failed to format output due to: {cause:?}")]
     FormatFailure { cause: Box<dyn std::error::Error> },
+    #[error("failed to load the column map, because of: {cause:?}")]
+    FailedToLoadColumnMap { cause: Box<dyn std::error::Error> },
+    #[error("the output has no column {column:?} to map")]
+    UnknownColumn { column: String },
+    #[error("invalid template, {0}")]
+    InvalidTemplate(String),
+    #[error("failed to read the template file, because of: {0}")]
+    FailedToLoadTemplate(std::io::Error),
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, EnumIter)]
@@ -23,11 +38,22 @@ pub enum OutputFormat {
     Csv,
     #[default]
     Json,
+    Yaml,
+    /// One compact JSON object per line, e.g. for piping listings into jq
+    Ndjson,
+    /// Aligned columns for reading in a terminal
+    Table,
+    /// Aligned columns with unicode borders
+    Grid,
+    /// Each record rendered with a user provided [`Template`]
+    ///
+    /// Formatters produce the CSV records here, which the template is applied to.
+    Template,
 }
 
 impl OutputFormat {
     pub fn names() -> Vec<&'static str> {
-        vec!["json", "csv"]
+        vec!["json", "csv", "yaml", "ndjson", "table", "grid", "template"]
     }
 }
 
@@ -36,6 +62,11 @@ impl std::fmt::Display for OutputFormat {
         match self {
             OutputFormat::Csv => write!(f, "csv"),
             OutputFormat::Json => write!(f, "json"),
+            OutputFormat::Yaml => write!(f, "yaml"),
+            OutputFormat::Ndjson => write!(f, "ndjson"),
+            OutputFormat::Table => write!(f, "table"),
+            OutputFormat::Grid => write!(f, "grid"),
+            OutputFormat::Template => write!(f, "template"),
         }
     }
 }
@@ -49,6 +80,11 @@ impl FromStr for OutputFormat {
         match normalized_format {
             JSON => Ok(OutputFormat::Json),
             CSV => Ok(OutputFormat::Csv),
+            YAML => Ok(OutputFormat::Yaml),
+            NDJSON => Ok(OutputFormat::Ndjson),
+            TABLE => Ok(OutputFormat::Table),
+            GRID => Ok(OutputFormat::Grid),
+            TEMPLATE => Ok(OutputFormat::Template),
             _ => Err(FormattingError::UnsupportedOutputFormat {
                 format: normalized_format.to_string(),
             }),
@@ -61,6 +97,24 @@ pub trait OutputFormatter {
     fn format(&self, format: OutputFormat) -> Result<String, FormattingError>;
 }
 
+/// Formats an item that is written as a single JSON object and as CSV records
+///
+/// This is the [`OutputFormatter`] implementation shared by all such types, so that the formats behave the same
+/// for every command.
+pub fn format_item<T>(item: &T, format: OutputFormat) -> Result<String, FormattingError>
+where
+    T: CsvRecordProducer + JsonProducer + YamlProducer + Serialize,
+{
+    match format {
+        OutputFormat::Json => item.to_json(),
+        OutputFormat::Csv | OutputFormat::Template => item.to_csv_with_header(),
+        OutputFormat::Yaml => item.to_yaml(),
+        OutputFormat::Ndjson => item.to_ndjson(),
+        OutputFormat::Table => item.to_table(false),
+        OutputFormat::Grid => item.to_table(true),
+    }
+}
+
 pub trait CsvRecordProducer {
     fn csv_header() -> Vec<String>;
 
@@ -77,11 +131,14 @@ pub trait CsvRecordProducer {
     fn to_csv(&self, with_header: bool) -> Result<String, FormattingError> {
         let buf = BufWriter::new(Vec::new());
         let mut wtr = Writer::from_writer(buf);
-        if with_header {
-            wtr.write_record(&Self::csv_header()).unwrap();
-        }
-        for record in self.as_csv_records() {
-            wtr.write_record(&record).unwrap();
+        let header = match with_header {
+            true => vec![Self::csv_header()],
+            false => Vec::new(),
+        };
+        for record in header.into_iter().chain(self.as_csv_records()) {
+            if let Err(e) = wtr.write_record(&record) {
+                return Err(FormattingError::FormatFailure { cause: Box::new(e) });
+            }
         }
         match wtr.flush() {
             Ok(_) => {
@@ -92,6 +149,89 @@ pub trait CsvRecordProducer {
             Err(e) => Err(FormattingError::FormatFailure { cause: Box::new(e) }),
         }
     }
+
+    /// Formats the CSV records as a table with aligned columns, optionally with unicode borders
+    fn to_table(&self, borders: bool) -> Result<String, FormattingError> {
+        Ok(format_table(
+            &Self::csv_header(),
+            &self.as_csv_records(),
+            borders,
+        ))
+    }
+}
+
+/// Lays out the header and records in aligned columns
+pub fn format_table(header: &Vec<String>, records: &Vec<Vec<String>>, borders: bool) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|cell| cell.chars().count()).collect();
+    for record in records {
+        for (index, cell) in record.iter().enumerate() {
+            let width = cell.chars().count();
+            match widths.get_mut(index) {
+                Some(current) if *current < width => *current = width,
+                Some(_) => (),
+                None => widths.push(width),
+            }
+        }
+    }
+
+    let row = |cells: &Vec<String>| -> String {
+        let cells: Vec<String> = widths
+            .iter()
+            .enumerate()
+            .map(|(index, width)| {
+                let cell = cells.get(index).map(String::as_str).unwrap_or_default();
+                format!("{}{}", cell, " ".repeat(width - cell.chars().count()))
+            })
+            .collect();
+        match borders {
+            true => format!("│ {} │", cells.join(" │ ")),
+            false => cells.join("  ").trim_end().to_string(),
+        }
+    };
+    let line = |left: &str, middle: &str, right: &str| -> String {
+        let segments: Vec<String> = widths.iter().map(|width| "─".repeat(width + 2)).collect();
+        format!("{}{}{}", left, segments.join(middle), right)
+    };
+
+    let mut lines = Vec::new();
+    if borders {
+        lines.push(line("┌", "┬", "┐"));
+    }
+    lines.push(row(header));
+    match borders {
+        true => lines.push(line("├", "┼", "┤")),
+        false => lines.push(
+            widths
+                .iter()
+                .map(|width| "-".repeat(*width))
+                .collect::<Vec<String>>()
+                .join("  "),
+        ),
+    }
+    for record in records {
+        lines.push(row(record));
+    }
+    if borders {
+        lines.push(line("└", "┴", "┘"));
+    }
+    lines.join("\n")
+}
+
+/// Lays out CSV output with a header row in aligned columns
+pub fn csv_to_table(csv: &str, borders: bool) -> Result<String, FormattingError> {
+    let mut reader = csv::Reader::from_reader(csv.as_bytes());
+    let header: Vec<String> = match reader.headers() {
+        Ok(headers) => headers.iter().map(String::from).collect(),
+        Err(e) => return Err(FormattingError::FormatFailure { cause: Box::new(e) }),
+    };
+    let mut records = Vec::new();
+    for record in reader.records() {
+        match record {
+            Ok(record) => records.push(record.iter().map(String::from).collect()),
+            Err(e) => return Err(FormattingError::FormatFailure { cause: Box::new(e) }),
+        }
+    }
+    Ok(format_table(&header, &records, borders))
 }
 
 pub trait JsonProducer {
@@ -105,4 +245,343 @@ pub trait JsonProducer {
             Err(e) => Err(FormattingError::FormatFailure { cause: Box::new(e) }),
         }
     }
+
+    fn to_ndjson(&self) -> Result<String, FormattingError>
+    where
+        Self: Serialize,
+    {
+        to_ndjson([self])
+    }
+}
+
+/// Writes each item as compact JSON on a line of its own
+pub fn to_ndjson<'a, T, I>(items: I) -> Result<String, FormattingError>
+where
+    T: Serialize + 'a + ?Sized,
+    I: IntoIterator<Item = &'a T>,
+{
+    let mut lines = Vec::new();
+    for item in items {
+        match serde_json::to_string(item) {
+            Ok(line) => lines.push(line),
+            Err(e) => return Err(FormattingError::FormatFailure { cause: Box::new(e) }),
+        }
+    }
+    Ok(lines.join("\n"))
+}
+
+pub trait YamlProducer {
+    fn to_yaml(&self) -> Result<String, FormattingError>
+    where
+        Self: Serialize,
+    {
+        let yaml = serde_yaml::to_string(&self);
+        match yaml {
+            Ok(yaml) => Ok(yaml),
+            Err(e) => Err(FormattingError::FormatFailure { cause: Box::new(e) }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TemplatePart {
+    Text(String),
+    Field(String),
+}
+
+/// Minimal template engine rendering one line per output record, e.g. `{{NAME}} -> {{ID}}`
+///
+/// Fields refer to the columns of the CSV output and are matched regardless of case.
+/// A literal `{{` is written as `{{{{`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<TemplatePart>,
+}
+
+impl FromStr for Template {
+    type Err = FormattingError;
+
+    fn from_str(template: &str) -> Result<Template, FormattingError> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            text.push_str(&rest[..start]);
+            rest = &rest[start + 2..];
+            if let Some(escaped) = rest.strip_prefix("{{") {
+                text.push_str("{{");
+                rest = escaped;
+                continue;
+            }
+
+            let end = match rest.find("}}") {
+                Some(end) => end,
+                None => {
+                    return Err(FormattingError::InvalidTemplate(
+                        "a field is missing its closing }}".to_string(),
+                    ))
+                }
+            };
+            let field = rest[..end].trim();
+            if field.is_empty() {
+                return Err(FormattingError::InvalidTemplate(
+                    "a field has no name".to_string(),
+                ));
+            }
+            if !text.is_empty() {
+                parts.push(TemplatePart::Text(std::mem::take(&mut text)));
+            }
+            parts.push(TemplatePart::Field(field.to_string()));
+            rest = &rest[end + 2..];
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            parts.push(TemplatePart::Text(text));
+        }
+
+        Ok(Template { parts })
+    }
+}
+
+impl Template {
+    pub fn load(path: &Path) -> Result<Template, FormattingError> {
+        match std::fs::read_to_string(path) {
+            Ok(template) => Template::from_str(template.trim_end_matches(['\r', '\n'])),
+            Err(e) => Err(FormattingError::FailedToLoadTemplate(e)),
+        }
+    }
+
+    /// Renders each record of CSV output with a header row
+    pub fn render(&self, csv: &str) -> Result<String, FormattingError> {
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        let headers = match reader.headers() {
+            Ok(headers) => headers.clone(),
+            Err(e) => return Err(FormattingError::FormatFailure { cause: Box::new(e) }),
+        };
+
+        let mut indexes = HashMap::new();
+        for part in &self.parts {
+            if let TemplatePart::Field(field) = part {
+                match headers
+                    .iter()
+                    .position(|header| header.eq_ignore_ascii_case(field))
+                {
+                    Some(index) => indexes.insert(field.clone(), index),
+                    None => {
+                        return Err(FormattingError::UnknownColumn {
+                            column: field.clone(),
+                        })
+                    }
+                };
+            }
+        }
+
+        let mut lines = Vec::new();
+        for record in reader.records() {
+            let record = match record {
+                Ok(record) => record,
+                Err(e) => return Err(FormattingError::FormatFailure { cause: Box::new(e) }),
+            };
+            let line: String = self
+                .parts
+                .iter()
+                .map(|part| match part {
+                    TemplatePart::Text(text) => text.as_str(),
+                    TemplatePart::Field(field) => record.get(indexes[field]).unwrap_or_default(),
+                })
+                .collect();
+            lines.push(line);
+        }
+        Ok(lines.join("\n"))
+    }
+}
+
+/// Output column of a [`ColumnMap`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColumnMapping {
+    /// Header of the column as produced by PCLI2
+    column: String,
+    /// Header written instead, defaults to the original one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    header: Option<String>,
+}
+
+/// Renames and reorders the columns of CSV output, e.g. to reproduce the reports of PCLI v1
+///
+/// The map is a YAML list of columns in output order. Columns that are not listed are left out:
+///
+/// ```yaml
+/// - column: NAME
+///   header: Folder Name
+/// - column: ID
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ColumnMap {
+    columns: Vec<ColumnMapping>,
+}
+
+impl ColumnMap {
+    /// Creates a map that only keeps the given columns, in the given order and with their original headers
+    pub fn from_fields(fields: &[String]) -> ColumnMap {
+        ColumnMap {
+            columns: fields
+                .iter()
+                .map(|field| field.trim())
+                .filter(|field| !field.is_empty())
+                .map(|field| ColumnMapping {
+                    column: field.to_string(),
+                    header: None,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<ColumnMap, FormattingError> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => match serde_yaml::from_str(&content) {
+                Ok(column_map) => Ok(column_map),
+                Err(e) => Err(FormattingError::FailedToLoadColumnMap { cause: Box::new(e) }),
+            },
+            Err(e) => Err(FormattingError::FailedToLoadColumnMap { cause: Box::new(e) }),
+        }
+    }
+
+    /// Rewrites CSV output with a header row according to the map
+    ///
+    /// Columns are matched regardless of case, and keep their original header unless the map renames them.
+    pub fn apply(&self, csv: &str) -> Result<String, FormattingError> {
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        let headers = match reader.headers() {
+            Ok(headers) => headers.clone(),
+            Err(e) => return Err(FormattingError::FormatFailure { cause: Box::new(e) }),
+        };
+
+        let mut indexes: Vec<usize> = Vec::new();
+        for mapping in &self.columns {
+            match headers
+                .iter()
+                .position(|header| header.eq_ignore_ascii_case(&mapping.column))
+            {
+                Some(index) => indexes.push(index),
+                None => {
+                    return Err(FormattingError::UnknownColumn {
+                        column: mapping.column.clone(),
+                    })
+                }
+            }
+        }
+
+        let buf = BufWriter::new(Vec::new());
+        let mut wtr = Writer::from_writer(buf);
+        let header: Vec<&str> = self
+            .columns
+            .iter()
+            .zip(&indexes)
+            .map(|(mapping, index)| match &mapping.header {
+                Some(header) => header.as_str(),
+                None => headers.get(*index).unwrap_or_default(),
+            })
+            .collect();
+        if let Err(e) = wtr.write_record(&header) {
+            return Err(FormattingError::FormatFailure { cause: Box::new(e) });
+        }
+        for record in reader.records() {
+            let record = match record {
+                Ok(record) => record,
+                Err(e) => return Err(FormattingError::FormatFailure { cause: Box::new(e) }),
+            };
+            let values: Vec<&str> = indexes
+                .iter()
+                .map(|index| record.get(*index).unwrap_or_default())
+                .collect();
+            if let Err(e) = wtr.write_record(&values) {
+                return Err(FormattingError::FormatFailure { cause: Box::new(e) });
+            }
+        }
+        let buf = match wtr.into_inner() {
+            Ok(buf) => buf,
+            Err(e) => return Err(FormattingError::FormatFailure { cause: Box::new(e) }),
+        };
+        let bytes = match buf.into_inner() {
+            Ok(bytes) => bytes,
+            Err(e) => return Err(FormattingError::FormatFailure { cause: Box::new(e) }),
+        };
+        match String::from_utf8(bytes) {
+            Ok(csv) => Ok(csv),
+            Err(e) => Err(FormattingError::FormatFailure { cause: Box::new(e) }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_column_map() {
+        let column_map: ColumnMap =
+            serde_yaml::from_str("- column: NAME\n  header: Folder Name\n- column: ID\n").unwrap();
+
+        let csv = "ID,NAME,OWNER\n1,\"first, folder\",me\n2,second,you\n";
+        assert_eq!(
+            "Folder Name,ID\n\"first, folder\",1\nsecond,2\n",
+            column_map.apply(csv).unwrap()
+        );
+
+        let column_map: ColumnMap = serde_yaml::from_str("- column: MISSING\n").unwrap();
+        match column_map.apply(csv) {
+            Err(FormattingError::UnknownColumn { column }) => assert_eq!("MISSING", column),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_column_map_from_fields() {
+        let fields = vec!["name".to_string(), " id".to_string()];
+        let column_map = ColumnMap::from_fields(&fields);
+
+        let csv = "ID,NAME,OWNER\n1,first,me\n2,second,you\n";
+        let csv = column_map.apply(csv).unwrap();
+        assert_eq!("NAME,ID\nfirst,1\nsecond,2\n", csv);
+        assert_eq!(
+            "NAME    ID\n------  --\nfirst   1\nsecond  2",
+            csv_to_table(&csv, false).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_template() {
+        let template = Template::from_str("{{name}} -> {{ ID }} {{{{literal}}").unwrap();
+        let csv = "ID,NAME\n1,first\n2,second\n";
+        assert_eq!(
+            "first -> 1 {{literal}}\nsecond -> 2 {{literal}}",
+            template.render(csv).unwrap()
+        );
+
+        assert!(Template::from_str("{{name").is_err());
+        assert!(Template::from_str("{{ }}").is_err());
+        match Template::from_str("{{uuid}}").unwrap().render(csv) {
+            Err(FormattingError::UnknownColumn { column }) => assert_eq!("uuid", column),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_format_table() {
+        let header = vec!["ID".to_string(), "NAME".to_string()];
+        let records = vec![
+            vec!["1".to_string(), "first".to_string()],
+            vec!["100".to_string(), "größer".to_string()],
+        ];
+
+        assert_eq!(
+            "ID   NAME\n---  ------\n1    first\n100  größer",
+            format_table(&header, &records, false)
+        );
+        assert_eq!(
+            "┌─────┬────────┐\n│ ID  │ NAME   │\n├─────┼────────┤\n│ 1   │ first  │\n│ 100 │ größer │\n└─────┴────────┘",
+            format_table(&header, &records, true)
+        );
+    }
 }
@@ -1,7 +1,11 @@
 pub mod api;
+pub mod arguments;
 pub mod client;
+pub mod color;
 pub mod commands;
 pub mod configuration;
+pub mod files;
+pub mod filter;
 pub mod format;
 pub mod model;
 pub mod security;
@@ -1,51 +1,581 @@
-use crate::format::{OutputFormat, OutputFormatter};
+use crate::format::{
+    csv_to_table, ColumnMap, FormattingError, OutputFormat, OutputFormatter, Template, TEMPLATE,
+};
 use api::Api;
 use commands::{
     create_cli_commands, COMMAND_CONFIG, COMMAND_DELETE, COMMAND_EXPORT, COMMAND_FOLDERS,
-    COMMAND_LOGIN, COMMAND_PATH, COMMAND_SET, COMMAND_SHOW, COMMAND_TENANT, PARAMETER_API_URL,
-    PARAMETER_CLIENT_ID, PARAMETER_CLIENT_SECRET, PARAMETER_FORMAT, PARAMETER_ID,
-    PARAMETER_OIDC_URL, PARAMETER_OUTPUT, PARAMETER_TENANT, PARAMETER_TENANT_ALIAS,
+    COMMAND_IMPORT, COMMAND_LOGIN, COMMAND_PATH, COMMAND_SET, COMMAND_SHOW, COMMAND_TENANT,
+    PARAMETER_API_URL, PARAMETER_CLIENT_CERT, PARAMETER_CLIENT_ID, PARAMETER_CLIENT_KEY,
+    PARAMETER_CLIENT_SECRET, PARAMETER_COLUMNS_MAP, PARAMETER_CONFIG, PARAMETER_DEFAULT,
+    PARAMETER_FIELDS, PARAMETER_FILTER, PARAMETER_FORMAT, PARAMETER_ID, PARAMETER_INCLUDE_SECRETS,
+    PARAMETER_INPUT, PARAMETER_OIDC_URL, PARAMETER_OUTPUT, PARAMETER_PROFILE,
+    PARAMETER_REFRESH_WINDOW, PARAMETER_STRICT_MODELS, PARAMETER_TEMPLATE, PARAMETER_TEMPLATE_FILE,
+    PARAMETER_TENANT, PARAMETER_TENANT_ALIAS,
+};
+use configuration::{
+    Configuration, ConfigurationError, ConfigurationExport, CredentialStoreConfiguration,
+    NetworkConfiguration, TenantConfiguration,
+};
+use pcli2::api::{ApiError, PcliExitCode};
+use pcli2::color::{paint_table_header, ColorChoice, Style};
+use pcli2::commands::{
+    COMMAND_AUTH, COMMAND_CHECK, COMMAND_CREDENTIAL_STORE, COMMAND_INIT, COMMAND_LOGOFF,
+    COMMAND_LOGOUT, COMMAND_NETWORK, COMMAND_REFRESH, COMMAND_STATUS, COMMAND_TOKEN,
+    COMMAND_WHOAMI, ENV_NO_INPUT, PARAMETER_ALL, PARAMETER_APPEND, PARAMETER_CA_FILE,
+    PARAMETER_COLOR, PARAMETER_DEVICE, PARAMETER_ERROR_FORMAT, PARAMETER_FALLBACK,
+    PARAMETER_KEY_FILE, PARAMETER_NO_INPUT, PARAMETER_NO_PROXY, PARAMETER_PATH,
+    PARAMETER_PROXY_URL, PARAMETER_PROXY_USERNAME, PARAMETER_REQUIRE, PARAMETER_TYPE,
+    PARAMETER_WIPE,
 };
-use configuration::{Configuration, ConfigurationError, TenantConfiguration};
-use pcli2::api::ApiError;
-use pcli2::commands::COMMAND_LOGOFF;
+use pcli2::files::write_atomically;
+use pcli2::filter::Filter;
+use pcli2::security::{ENV_ACCESS_TOKEN, ENV_CLIENT_SECRET, ENV_PASSPHRASE};
+use serde::Serialize;
 use std::cell::RefCell;
+use std::io::{IsTerminal, Write};
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Duration;
 use thiserror::Error;
 use url::Url;
 
 use pcli2::{api, commands, configuration, format};
 
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 enum PcliError {
-    #[error("configuration error")]
+    #[error("configuration error: {message}")]
     ConfigurationError { message: String },
-    #[error("API error")]
+    #[error("credential store error: {message}")]
+    CredentialStoreError { message: String },
+    #[error("usage error: {message}")]
+    UsageError { message: String },
+    #[error("API error: {0}")]
     ApiError(#[from] ApiError),
 }
 
+impl PcliError {
+    fn exit_code(&self) -> exitcode::ExitCode {
+        match self {
+            PcliError::ConfigurationError { .. } => exitcode::CONFIG,
+            PcliError::CredentialStoreError { .. } => PcliExitCode::CredentialStoreError.into(),
+            PcliError::UsageError { .. } => exitcode::USAGE,
+            PcliError::ApiError(e) => PcliExitCode::from(e).into(),
+        }
+    }
+}
+
 impl From<ConfigurationError> for PcliError {
     fn from(error: ConfigurationError) -> PcliError {
-        PcliError::ConfigurationError {
-            message: format!("{}", error.to_string()),
+        match error {
+            ConfigurationError::KeyringError(e) => PcliError::CredentialStoreError {
+                message: e.to_string(),
+            },
+            _ => PcliError::ConfigurationError {
+                message: error.to_string(),
+            },
+        }
+    }
+}
+
+/// Whether prompts are disabled by --no-input or PCLI2_NO_INPUT, set once the command line has been parsed
+static NO_INPUT: OnceLock<bool> = OnceLock::new();
+
+/// Returns whether the command line or the environment disables prompts
+fn no_input(matches: &clap::ArgMatches) -> bool {
+    matches.get_flag(PARAMETER_NO_INPUT)
+        || std::env::var(ENV_NO_INPUT).is_ok_and(|value| !value.is_empty() && value != "0")
+}
+
+/// Fails instead of prompting when prompts are disabled, telling what to supply instead
+fn check_input_allowed(alternative: &str) -> Result<(), PcliError> {
+    match NO_INPUT.get() {
+        Some(true) => Err(PcliError::UsageError {
+            message: format!(
+                "prompts are disabled by --no-input or {}, {}",
+                ENV_NO_INPUT, alternative
+            ),
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn read_passphrase(confirm: bool) -> Result<String, PcliError> {
+    if let Ok(passphrase) = std::env::var(ENV_PASSPHRASE) {
+        return Ok(passphrase);
+    }
+    check_input_allowed(&format!("set the passphrase with {}", ENV_PASSPHRASE))?;
+
+    let prompt = |message: &str| match rpassword::prompt_password(message) {
+        Ok(passphrase) => Ok(passphrase),
+        Err(e) => Err(PcliError::ConfigurationError {
+            message: format!("failed to read the passphrase: {}", e),
+        }),
+    };
+
+    let passphrase = prompt("Passphrase: ")?;
+    if confirm && passphrase != prompt("Confirm passphrase: ")? {
+        return Err(PcliError::ConfigurationError {
+            message: "the passphrases do not match".to_string(),
+        });
+    }
+
+    Ok(passphrase)
+}
+
+/// Returns the alias of the tenant given on the command line, or the default tenant of the selected profile
+///
+/// Every command resolves the tenant here, so that it can be given by its alias or by its tenant ID alike.
+fn tenant_alias(
+    sub_matches: &clap::ArgMatches,
+    configuration: &RefCell<Configuration>,
+) -> Result<String, PcliError> {
+    match sub_matches.get_one::<String>(PARAMETER_TENANT) {
+        Some(tenant) => Ok(configuration.borrow().resolve_tenant(tenant)?),
+        None => match configuration.borrow().default_tenant() {
+            Some(tenant) => Ok(tenant),
+            None => Err(PcliError::ConfigurationError {
+                message: "no tenant specified and no default tenant configured".to_string(),
+            }),
+        },
+    }
+}
+
+/// Returns the output format given on the command line, or the configured one
+///
+/// A template given without a format implies the template format. Without either, a table is printed to a terminal and JSON otherwise, so that scripts keep getting JSON.
+fn output_format(
+    sub_matches: &clap::ArgMatches,
+    configuration: &RefCell<Configuration>,
+) -> OutputFormat {
+    match sub_matches.get_one::<String>(PARAMETER_FORMAT) {
+        // the parser only accepts the names of the supported formats
+        Some(format) => OutputFormat::from_str(format).unwrap(),
+        None if sub_matches.contains_id(PARAMETER_TEMPLATE)
+            || sub_matches.contains_id(PARAMETER_TEMPLATE_FILE) =>
+        {
+            OutputFormat::Template
+        }
+        None => match configuration.borrow().output_format() {
+            Some(format) => format,
+            None if std::io::stdout().is_terminal() => OutputFormat::Table,
+            None => OutputFormat::Json,
+        },
+    }
+}
+
+/// Returns the filter given with the --filter options of a list command
+fn list_filter(sub_matches: &clap::ArgMatches) -> Result<Filter, PcliError> {
+    let expressions: Vec<&String> = match sub_matches.get_many::<String>(PARAMETER_FILTER) {
+        Some(expressions) => expressions.collect(),
+        None => Vec::new(),
+    };
+    match Filter::parse(&expressions) {
+        Ok(filter) => Ok(filter),
+        Err(e) => Err(PcliError::ConfigurationError {
+            message: e.to_string(),
+        }),
+    }
+}
+
+/// Reads a line from the standard input after printing the prompt, falling back to the default on an empty answer
+fn prompt(message: &str, default: Option<&str>) -> Result<String, PcliError> {
+    loop {
+        match default {
+            Some(default) => eprint!("{} [{}]: ", message, default),
+            None => eprint!("{}: ", message),
+        }
+        let _ = std::io::stderr().flush();
+
+        let mut answer = String::new();
+        match std::io::stdin().read_line(&mut answer) {
+            Ok(0) => {
+                return Err(PcliError::ConfigurationError {
+                    message: "the input ended before the setup was completed".to_string(),
+                })
+            }
+            Ok(_) => (),
+            Err(e) => {
+                return Err(PcliError::ConfigurationError {
+                    message: format!("failed to read the answer: {}", e),
+                })
+            }
+        }
+
+        match (answer.trim(), default) {
+            ("", Some(default)) => return Ok(default.to_string()),
+            ("", None) => continue,
+            (answer, _) => return Ok(answer.to_string()),
+        }
+    }
+}
+
+/// Prompts until the answer can be parsed
+fn prompt_parsed<T: FromStr>(message: &str, default: Option<&str>) -> Result<T, PcliError>
+where
+    T::Err: std::fmt::Display,
+{
+    loop {
+        match prompt(message, default)?.parse::<T>() {
+            Ok(value) => return Ok(value),
+            Err(e) => eprintln!("Invalid value: {}", e),
+        }
+    }
+}
+
+/// Lets the user pick one of the options by its number or by its name
+fn select(message: &str, options: &[String], default: &str) -> Result<String, PcliError> {
+    for (index, option) in options.iter().enumerate() {
+        eprintln!("  {}) {}", index + 1, option);
+    }
+    loop {
+        let answer = prompt(message, Some(default))?;
+        if options.contains(&answer) {
+            return Ok(answer);
+        }
+        match answer.parse::<usize>() {
+            Ok(number) if number >= 1 && number <= options.len() => {
+                return Ok(options[number - 1].clone())
+            }
+            _ => eprintln!("Please enter one of the numbers or names listed above."),
+        }
+    }
+}
+
+/// Login method of the init wizard using the client ID and secret
+const LOGIN_CLIENT_CREDENTIALS: &str = "client-credentials";
+/// Login method of the init wizard using the device authorization flow, see `auth login --device`
+const LOGIN_DEVICE: &str = "device";
+
+/// Walks through the configuration of a tenant, the default tenant and the output format, and saves them
+fn init(configuration: &RefCell<Configuration>) -> Result<(), PcliError> {
+    check_input_allowed("use \"config set tenant\" to configure a tenant instead")?;
+    eprintln!("This will add a tenant to the configuration and select the defaults.");
+
+    let tenant_id = prompt("Tenant ID", None)?;
+    let alias = prompt("Alias", Some(&tenant_id))?;
+    let api_url: Url = prompt_parsed(
+        "API URL",
+        Some(&format!("https://{}.physna.com/api/v2", tenant_id)),
+    )?;
+    let oidc_url: Url = prompt_parsed("OpenID Connect identity provider URL", None)?;
+    let client_id = prompt("Client ID", None)?;
+
+    // users logging in with the device flow have no client secret, and a read-only store cannot keep one
+    let login_methods = vec![
+        LOGIN_CLIENT_CREDENTIALS.to_string(),
+        LOGIN_DEVICE.to_string(),
+    ];
+    let login_method = select("Login method", &login_methods, LOGIN_CLIENT_CREDENTIALS)?;
+    let read_only = configuration
+        .borrow()
+        .credential_store()
+        .store()
+        .is_read_only();
+    let client_secret = match (login_method.as_str(), read_only) {
+        (LOGIN_DEVICE, _) => None,
+        (_, true) => {
+            eprintln!(
+                "The credential store only reads the client secret from {}.",
+                ENV_CLIENT_SECRET
+            );
+            None
+        }
+        _ => match rpassword::prompt_password("Client secret: ") {
+            Ok(client_secret) => Some(client_secret),
+            Err(e) => {
+                return Err(PcliError::ConfigurationError {
+                    message: format!("failed to read the client secret: {}", e),
+                })
+            }
+        },
+    };
+
+    let mut builder = TenantConfiguration::builder();
+    builder
+        .tenant_id(tenant_id)
+        .api_url(api_url)
+        .oidc_url(oidc_url)
+        .client_id(client_id)
+        .profile(configuration.borrow().profile())
+        .credential_store(configuration.borrow().credential_store());
+    if let Some(client_secret) = client_secret {
+        builder.client_secret(client_secret);
+    }
+    let tenant = builder.build()?;
+
+    let mut aliases = configuration.borrow().get_all_tenant_aliases();
+    if !aliases.contains(&alias) {
+        aliases.push(alias.clone());
+    }
+    aliases.sort();
+    let default_tenant = select("Default tenant", &aliases, &alias)?;
+
+    // a template is specific to a command, so it cannot be the default
+    let formats: Vec<String> = OutputFormat::names()
+        .into_iter()
+        .filter(|name| *name != TEMPLATE)
+        .map(|name| name.to_string())
+        .collect();
+    let output_format = select(
+        "Default output format",
+        &formats,
+        &configuration
+            .borrow()
+            .output_format()
+            .unwrap_or_default()
+            .to_string(),
+    )?;
+    let output_format = OutputFormat::from_str(&output_format).unwrap();
+
+    configuration.borrow_mut().update_default(|configuration| {
+        configuration.add_tenant(Some(&alias), &tenant)?;
+        configuration.set_default_tenant(Some(&default_tenant));
+        configuration.set_output_format(Some(output_format.clone()));
+        Ok(())
+    })?;
+
+    eprintln!(
+        "Configuration written to {}",
+        configuration.borrow().file_path()?.display()
+    );
+    if login_method == LOGIN_DEVICE {
+        eprintln!("Run \"pcli2 auth login --device\" to login.");
+    }
+    Ok(())
+}
+
+/// Returns the --output file, which the export commands require
+fn output_path(sub_matches: &clap::ArgMatches) -> Result<&PathBuf, PcliError> {
+    match sub_matches.get_one::<PathBuf>(PARAMETER_OUTPUT) {
+        Some(path) => Ok(path),
+        None => Err(PcliError::ConfigurationError {
+            message: "the export requires an --output file".to_string(),
+        }),
+    }
+}
+
+/// Options of the command line that post-process the formatted output
+struct OutputOptions {
+    column_map: Option<ColumnMap>,
+    template: Option<Template>,
+    /// File the output is written to instead of the standard output
+    file: Option<PathBuf>,
+    append: bool,
+    /// Whether the output goes to a terminal that should be colored
+    color: bool,
+}
+
+/// Writes the output to the file, replacing it atomically unless appending to it
+fn write_output(path: &PathBuf, output: &str, append: bool) -> std::io::Result<()> {
+    if append {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        return writeln!(file, "{}", output);
+    }
+
+    write_atomically(path, format!("{}\n", output).as_bytes())
+}
+
+/// Prints the output, or writes it to the --output file
+fn print_text(output: &str, options: &OutputOptions) {
+    match &options.file {
+        Some(path) => {
+            if let Err(e) = write_output(path, output, options.append) {
+                exit_with_error(
+                    format!("failed to write to {}: {}", path.display(), e).as_str(),
+                    exitcode::CANTCREAT,
+                )
+            }
         }
+        None => println!("{}", output),
     }
 }
 
+/// Prints the formatted output
+///
+/// A column map renames, reorders or selects the columns of CSV and table output, and of the records rendered with a template.
+fn print_output<T: OutputFormatter>(item: &T, format: OutputFormat, options: &OutputOptions) {
+    let csv = || {
+        let csv = item.format(OutputFormat::Csv);
+        match &options.column_map {
+            Some(column_map) => csv.and_then(|csv| column_map.apply(&csv)),
+            None => csv,
+        }
+    };
+    let output = match (&format, &options.column_map) {
+        (OutputFormat::Template, _) => match &options.template {
+            Some(template) => csv().and_then(|csv| template.render(&csv)),
+            None => Err(FormattingError::InvalidTemplate(
+                "use --template or --template-file with the template format".to_string(),
+            )),
+        },
+        (OutputFormat::Csv, Some(_)) => csv(),
+        (OutputFormat::Table | OutputFormat::Grid, Some(_)) => {
+            csv().and_then(|csv| csv_to_table(&csv, format == OutputFormat::Grid))
+        }
+        _ => item.format(format.clone()),
+    };
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => return exit_with_error(e.to_string().as_str(), exitcode::CONFIG),
+    };
+    let output = match (&format, options.color) {
+        (OutputFormat::Table, true) => paint_table_header(&output, false),
+        (OutputFormat::Grid, true) => paint_table_header(&output, true),
+        _ => output,
+    };
+    print_text(&output, options);
+}
+
+/// How errors are reported, once the command line has been parsed
+struct ErrorReporting {
+    color: ColorChoice,
+    json: bool,
+    /// Names of the command and subcommands that were run, e.g. "auth status"
+    command: String,
+}
+
+static ERROR_REPORTING: OnceLock<ErrorReporting> = OnceLock::new();
+
+/// Error written to the standard error with `--error-format json`
+#[derive(Serialize)]
+struct ErrorReport<'a> {
+    code: exitcode::ExitCode,
+    message: &'a str,
+    context: ErrorContext<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorContext<'a> {
+    command: &'a str,
+}
+
 fn exit_with_error(message: &str, code: exitcode::ExitCode) {
-    eprintln!("ERROR: {}", message);
+    match ERROR_REPORTING.get() {
+        Some(reporting) if reporting.json => {
+            let report = ErrorReport {
+                code,
+                message,
+                context: ErrorContext {
+                    command: &reporting.command,
+                },
+            };
+            match serde_json::to_string(&report) {
+                Ok(report) => eprintln!("{}", report),
+                Err(_) => eprintln!("ERROR: {}", message),
+            }
+        }
+        reporting => {
+            let color = reporting
+                .map(|reporting| reporting.color)
+                .unwrap_or_default();
+            match color.enabled(std::io::stderr().is_terminal()) {
+                true => eprintln!("{} {}", Style::Red.paint("ERROR:"), message),
+                false => eprintln!("ERROR: {}", message),
+            }
+        }
+    }
     ::std::process::exit(code);
 }
 
-fn main() -> Result<(), PcliError> {
+/// Returns the names of the subcommands selected on the command line, separated by spaces
+fn command_path(matches: &clap::ArgMatches) -> String {
+    let mut names = Vec::new();
+    let mut matches = matches;
+    while let Some((name, sub_matches)) = matches.subcommand() {
+        names.push(name);
+        matches = sub_matches;
+    }
+    names.join(" ")
+}
+
+fn main() {
     // initialize the log
     let _log_init_result = pretty_env_logger::try_init_timed();
-    let configuration = RefCell::new(Configuration::load_default().unwrap_or_default());
-    let api = Api::new(&configuration);
+
+    if let Err(e) = run() {
+        exit_with_error(e.to_string().as_str(), e.exit_code());
+    }
+}
+
+fn run() -> Result<(), PcliError> {
     let commands = create_cli_commands();
+    let color = ERROR_REPORTING
+        .get_or_init(|| ErrorReporting {
+            // the parser only accepts the names of the choices
+            color: ColorChoice::from_str(commands.get_one::<String>(PARAMETER_COLOR).unwrap())
+                .unwrap(),
+            json: commands
+                .get_one::<String>(PARAMETER_ERROR_FORMAT)
+                .map(String::as_str)
+                == Some("json"),
+            command: command_path(&commands),
+        })
+        .color;
+    let _ = NO_INPUT.set(no_input(&commands));
+    let configuration = match commands.get_one::<PathBuf>(PARAMETER_CONFIG) {
+        Some(path) => Configuration::load_from(path)?,
+        None => Configuration::load_default().unwrap_or_default(),
+    };
+    let configuration = RefCell::new(configuration);
+    configuration
+        .borrow_mut()
+        .select_profile(commands.get_one::<String>(PARAMETER_PROFILE));
+    let client_cert = commands.get_one::<PathBuf>(PARAMETER_CLIENT_CERT).cloned();
+    let client_key = commands.get_one::<PathBuf>(PARAMETER_CLIENT_KEY).cloned();
+    let mut api = Api::new(&configuration);
+    if client_cert.is_some() {
+        // applies to this invocation only, the configuration is left as it is stored
+        api.set_client_identity(client_cert.clone(), client_key.clone());
+    }
+    api.set_strict_models(commands.get_flag(PARAMETER_STRICT_MODELS));
+    if let Some(window) = commands.get_one::<Duration>(PARAMETER_REFRESH_WINDOW) {
+        api.set_token_refresh_window(window.as_secs());
+    }
+    let column_map = match commands.get_one::<PathBuf>(PARAMETER_COLUMNS_MAP) {
+        Some(path) => match ColumnMap::load(path) {
+            Ok(column_map) => Some(column_map),
+            Err(e) => {
+                return Err(PcliError::ConfigurationError {
+                    message: e.to_string(),
+                })
+            }
+        },
+        None => commands
+            .get_many::<String>(PARAMETER_FIELDS)
+            .map(|fields| ColumnMap::from_fields(&fields.cloned().collect::<Vec<String>>())),
+    };
+
+    let template = match commands.get_one::<String>(PARAMETER_TEMPLATE) {
+        Some(template) => Some(Template::from_str(template)),
+        None => commands
+            .get_one::<PathBuf>(PARAMETER_TEMPLATE_FILE)
+            .map(|path| Template::load(path)),
+    };
+    let template = match template.transpose() {
+        Ok(template) => template,
+        Err(e) => {
+            return Err(PcliError::ConfigurationError {
+                message: e.to_string(),
+            })
+        }
+    };
+    let file = commands.get_one::<PathBuf>(PARAMETER_OUTPUT).cloned();
+    let output_options = OutputOptions {
+        column_map,
+        template,
+        color: file.is_none() && color.enabled(std::io::stdout().is_terminal()),
+        file,
+        append: commands.get_flag(PARAMETER_APPEND),
+    };
 
     match commands.subcommand() {
+        // First run
+        Some((COMMAND_INIT, _)) => init(&configuration)?,
         // Configuration
         Some((COMMAND_CONFIG, sub_matches)) => match sub_matches.subcommand() {
             Some((COMMAND_SET, sub_matches)) => match sub_matches.subcommand() {
@@ -55,63 +585,130 @@ fn main() -> Result<(), PcliError> {
                     let api_url = sub_matches.get_one::<Url>(PARAMETER_API_URL).unwrap();
                     let oidc_url = sub_matches.get_one::<Url>(PARAMETER_OIDC_URL).unwrap();
                     let client_id = sub_matches.get_one::<String>(PARAMETER_CLIENT_ID).unwrap();
-                    let client_secret = sub_matches
-                        .get_one::<String>(PARAMETER_CLIENT_SECRET)
-                        .unwrap();
+                    let client_secret = sub_matches.get_one::<String>(PARAMETER_CLIENT_SECRET);
+                    let default = sub_matches.get_flag(PARAMETER_DEFAULT);
 
-                    let tenant = TenantConfiguration::builder()
+                    let mut builder = TenantConfiguration::builder();
+                    builder
                         .tenant_id(id.to_owned())
                         .api_url(api_url.to_owned())
                         .oidc_url(oidc_url.to_owned())
                         .client_id(client_id.to_owned())
-                        .client_secret(client_secret.to_owned())
-                        .build()?;
+                        .profile(configuration.borrow().profile())
+                        .credential_store(configuration.borrow().credential_store());
+                    if let Some(client_secret) = client_secret {
+                        builder.client_secret(client_secret.to_owned());
+                    }
+                    let tenant = builder.build()?;
+
+                    configuration.borrow_mut().update_default(|configuration| {
+                        configuration.add_tenant(alias, &tenant)?;
+                        if default {
+                            configuration.set_default_tenant(Some(alias.unwrap_or(id)));
+                        }
+                        Ok(())
+                    })?;
+                }
+                Some((COMMAND_CREDENTIAL_STORE, sub_matches)) => {
+                    let encrypted_file = || CredentialStoreConfiguration::EncryptedFile {
+                        path: sub_matches.get_one::<PathBuf>(PARAMETER_PATH).cloned(),
+                        key_file: sub_matches.get_one::<PathBuf>(PARAMETER_KEY_FILE).cloned(),
+                    };
+                    let fallback = match sub_matches
+                        .get_one::<String>(PARAMETER_FALLBACK)
+                        .map(String::as_str)
+                    {
+                        Some("encrypted-file") => Some(Box::new(encrypted_file())),
+                        Some("environment") => {
+                            Some(Box::new(CredentialStoreConfiguration::Environment))
+                        }
+                        _ => None,
+                    };
+                    let store_type = sub_matches.get_one::<String>(PARAMETER_TYPE).unwrap();
+                    let credential_store = match (store_type.as_str(), fallback) {
+                        ("keyring", fallback) => CredentialStoreConfiguration::Keyring { fallback },
+                        (_, Some(_)) => {
+                            return Err(PcliError::ConfigurationError {
+                                message: "--fallback only applies to --type keyring".to_string(),
+                            })
+                        }
+                        ("encrypted-file", None) => encrypted_file(),
+                        _ => CredentialStoreConfiguration::Environment,
+                    };
 
-                    configuration.borrow_mut().add_tenant(alias, &tenant)?;
-                    configuration.borrow().save_to_default()?;
+                    configuration.borrow_mut().update_default(|configuration| {
+                        configuration.set_credential_store(credential_store.clone());
+                        Ok(())
+                    })?;
+                }
+                Some((COMMAND_NETWORK, sub_matches)) => {
+                    let mut network = NetworkConfiguration::new(
+                        sub_matches.get_one::<Url>(PARAMETER_PROXY_URL).cloned(),
+                        sub_matches
+                            .get_one::<String>(PARAMETER_PROXY_USERNAME)
+                            .cloned(),
+                        sub_matches.get_one::<String>(PARAMETER_NO_PROXY).cloned(),
+                        sub_matches.get_one::<PathBuf>(PARAMETER_CA_FILE).cloned(),
+                    );
+                    network.set_client_identity(client_cert.clone(), client_key.clone());
+
+                    configuration.borrow_mut().update_default(|configuration| {
+                        configuration.set_network(network.clone());
+                        Ok(())
+                    })?;
                 }
                 _ => unreachable!("Invalid subcommand for 'config set"),
             },
             Some((COMMAND_EXPORT, sub_matches)) => {
-                let path = sub_matches.get_one::<PathBuf>(PARAMETER_OUTPUT).unwrap(); // it is save vefause the argument is mandatory
-                configuration.borrow().save(path)?;
+                let path = output_path(sub_matches)?;
+                let passphrase = match sub_matches.get_flag(PARAMETER_INCLUDE_SECRETS) {
+                    true => Some(read_passphrase(true)?),
+                    false => None,
+                };
+                configuration.borrow().export(path, passphrase.as_deref())?;
+            }
+            Some((COMMAND_IMPORT, sub_matches)) => {
+                let path = sub_matches.get_one::<PathBuf>(PARAMETER_INPUT).unwrap();
+                let export = ConfigurationExport::load(path)?;
+                let passphrase = match export.has_secrets() {
+                    true => Some(read_passphrase(false)?),
+                    false => None,
+                };
+                configuration.borrow_mut().update_default(|configuration| {
+                    configuration.import(&export, passphrase.as_deref())
+                })?;
             }
             Some((COMMAND_SHOW, sub_matches)) => match sub_matches.subcommand() {
                 Some((COMMAND_PATH, _)) => {
-                    let path = Configuration::get_default_configuration_file_path()?;
+                    let path = configuration.borrow().file_path()?;
                     let path = path.into_os_string().into_string().unwrap();
-                    println!("{}", path);
+                    print_text(&path, &output_options);
                 }
                 Some((COMMAND_TENANT, sub_matches)) => {
-                    let format = sub_matches.get_one::<String>(PARAMETER_FORMAT).unwrap();
-                    let format = OutputFormat::from_str(format).unwrap();
+                    let format = output_format(sub_matches, &configuration);
 
                     let id = sub_matches.get_one::<String>(PARAMETER_ID).unwrap();
                     match configuration.borrow().tenant(id) {
-                        Some(tenant) => match tenant.format(format) {
-                            Ok(output) => println!("{}", output),
-                            Err(e) => exit_with_error(e.to_string().as_str(), exitcode::CONFIG),
-                        },
+                        Some(tenant) => print_output(&tenant, format, &output_options),
                         None => (),
                     }
                 }
                 _ => {
                     // print all tenants
-                    let format = sub_matches.get_one::<String>(PARAMETER_FORMAT).unwrap();
-                    let format = OutputFormat::from_str(format).unwrap();
+                    let format = output_format(sub_matches, &configuration);
 
-                    match configuration.borrow().format(format) {
-                        Ok(output) => println!("{}", output),
-                        Err(e) => exit_with_error(e.to_string().as_str(), exitcode::CONFIG),
-                    }
+                    print_output(&*configuration.borrow(), format, &output_options);
                 }
             },
             Some((COMMAND_DELETE, sub_matches)) => match sub_matches.subcommand() {
                 Some((COMMAND_TENANT, sub_matches)) => {
                     let alias = sub_matches.get_one::<String>(PARAMETER_ID).unwrap();
-                    configuration.borrow_mut().delete_tenant(alias);
-                    match configuration.borrow().save_to_default() {
-                        Ok(()) => (),
+                    let result = configuration.borrow_mut().update_default(|configuration| {
+                        configuration.delete_tenant(alias);
+                        Ok(())
+                    });
+                    match result {
+                        Ok(_) => (),
                         Err(e) => exit_with_error(e.to_string().as_str(), exitcode::IOERR),
                     }
                 }
@@ -121,32 +718,192 @@ fn main() -> Result<(), PcliError> {
         },
         // Folders
         Some((COMMAND_FOLDERS, sub_matches)) => {
-            let tenant = sub_matches.get_one::<String>(PARAMETER_TENANT).unwrap();
-            let format = sub_matches.get_one::<String>(PARAMETER_FORMAT).unwrap();
-            let format = OutputFormat::from_str(format).unwrap();
+            let tenant = tenant_alias(sub_matches, &configuration)?;
+            let format = output_format(sub_matches, &configuration);
+            let filter = list_filter(sub_matches)?;
             let folders = api.list_folders(&tenant);
 
             match folders {
-                Ok(folders) => match folders.format(format) {
-                    Ok(output) => println!("{}", output),
-                    Err(e) => exit_with_error(e.to_string().as_str(), exitcode::CONFIG),
-                },
-                Err(e) => exit_with_error(&e.to_string(), exitcode::DATAERR),
+                Ok(mut folders) => {
+                    folders.retain(|folder| filter.matches(folder));
+                    print_output(&folders, format, &output_options)
+                }
+                Err(e) => exit_with_error(&e.to_string(), PcliExitCode::from(&e).into()),
             }
         }
         // Login
         Some((COMMAND_LOGIN, sub_matches)) => {
-            let tenant = sub_matches.get_one::<String>(PARAMETER_TENANT).unwrap();
-            let _ = api.login(tenant)?;
+            let tenant = tenant_alias(sub_matches, &configuration)?;
+            let _ = api.login(&tenant)?;
         }
         // Logoff
         Some((COMMAND_LOGOFF, sub_matches)) => {
-            let tenant = sub_matches.get_one::<String>(PARAMETER_TENANT).unwrap();
-            api.logoff(tenant)?;
+            let tenant = tenant_alias(sub_matches, &configuration)?;
+            api.logoff(&tenant)?;
         }
+        // Authentication
+        Some((COMMAND_AUTH, sub_matches)) => match sub_matches.subcommand() {
+            Some((COMMAND_LOGIN, sub_matches)) => {
+                let tenant = tenant_alias(sub_matches, &configuration)?;
+                if sub_matches.get_flag(PARAMETER_DEVICE) {
+                    let _ = api.device_login(&tenant, |authorization| {
+                        match &authorization.verification_uri_complete {
+                            Some(uri) => eprintln!("To login, open {} in a browser.", uri),
+                            None => eprintln!(
+                                "To login, open {} in a browser and enter the code {}.",
+                                authorization.verification_uri, authorization.user_code
+                            ),
+                        }
+                    })?;
+                } else {
+                    let _ = api.login(&tenant)?;
+                }
+            }
+            Some((COMMAND_LOGOUT, sub_matches)) => {
+                let wipe = sub_matches.get_flag(PARAMETER_WIPE);
+                if wipe
+                    && configuration
+                        .borrow()
+                        .credential_store()
+                        .store()
+                        .is_read_only()
+                {
+                    eprintln!(
+                        "Credentials provided through the environment are not removed, unset {} and {} instead.",
+                        ENV_CLIENT_SECRET, ENV_ACCESS_TOKEN
+                    );
+                }
+                if sub_matches.get_flag(PARAMETER_ALL) {
+                    api.logout_all(wipe)?;
+                    if wipe {
+                        configuration.borrow_mut().update_default(|configuration| {
+                            configuration.delete_all_profiles();
+                            Ok(())
+                        })?;
+                    }
+                } else {
+                    let tenant = tenant_alias(sub_matches, &configuration)?;
+                    api.logout(&tenant, wipe)?;
+                    if wipe {
+                        configuration.borrow_mut().update_default(|configuration| {
+                            configuration.delete_tenant(&tenant);
+                            Ok(())
+                        })?;
+                    }
+                }
+            }
+            Some((COMMAND_STATUS, sub_matches)) => {
+                let tenant = tenant_alias(sub_matches, &configuration)?;
+                let format = output_format(sub_matches, &configuration);
+
+                let status = api.auth_status(&tenant)?;
+                print_output(&status, format, &output_options);
+            }
+            Some((COMMAND_CHECK, sub_matches)) => {
+                let tenant = tenant_alias(sub_matches, &configuration)?;
+                let required: Vec<&str> = sub_matches
+                    .get_many::<String>(PARAMETER_REQUIRE)
+                    .unwrap()
+                    .map(|scope| scope.trim())
+                    .filter(|scope| !scope.is_empty())
+                    .collect();
+
+                api.check_scopes(&tenant, &required)?;
+            }
+            Some((COMMAND_EXPORT, sub_matches)) => {
+                let path = output_path(sub_matches)?;
+                let passphrase = read_passphrase(true)?;
+                configuration.borrow().export_encrypted(path, &passphrase)?;
+            }
+            Some((COMMAND_IMPORT, sub_matches)) => {
+                let path = sub_matches.get_one::<PathBuf>(PARAMETER_INPUT).unwrap();
+                let passphrase = read_passphrase(false)?;
+                let export = ConfigurationExport::load_encrypted(path, &passphrase)?;
+                configuration.borrow_mut().update_default(|configuration| {
+                    configuration.import(&export, Some(&passphrase))
+                })?;
+            }
+            Some((COMMAND_TOKEN, sub_matches)) => match sub_matches.subcommand() {
+                Some((COMMAND_REFRESH, sub_matches)) => {
+                    let tenant = tenant_alias(sub_matches, &configuration)?;
+                    let format = output_format(sub_matches, &configuration);
+
+                    let status = api.refresh_token(&tenant)?;
+                    print_output(&status, format, &output_options);
+                }
+                _ => unreachable!("Invalid subcommand for 'auth token'"),
+            },
+            Some((COMMAND_WHOAMI, sub_matches)) => {
+                let tenant = tenant_alias(sub_matches, &configuration)?;
+                let format = output_format(sub_matches, &configuration);
+
+                let user = api.current_user(&tenant)?;
+                print_output(&user, format, &output_options);
+            }
+            _ => unreachable!("Invalid subcommand for 'auth'"),
+        },
         _ => unreachable!("Invalid command"),
     }
 
     // exit normally with status code of zero
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pcli2::commands::create_cli;
+
+    #[test]
+    fn test_error_report() {
+        let report = ErrorReport {
+            code: PcliExitCode::TokenExpired.into(),
+            message: "the token has expired",
+            context: ErrorContext {
+                command: "auth status",
+            },
+        };
+        assert_eq!(
+            serde_json::json!({
+                "code": 82,
+                "message": "the token has expired",
+                "context": { "command": "auth status" }
+            }),
+            serde_json::to_value(&report).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_write_output() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("output.txt");
+
+        write_output(&path, "first", false).unwrap();
+        write_output(&path, "second", true).unwrap();
+        assert_eq!("first\nsecond\n", std::fs::read_to_string(&path).unwrap());
+
+        write_output(&path, "third", false).unwrap();
+        assert_eq!("third\n", std::fs::read_to_string(&path).unwrap());
+    }
+
+    #[test]
+    fn test_command_path() {
+        let matches = create_cli()
+            .try_get_matches_from(["pcli2", "auth", "token", "refresh", "--format", "json"])
+            .unwrap();
+        assert_eq!("auth token refresh", command_path(&matches));
+
+        let matches = create_cli()
+            .try_get_matches_from(["pcli2", "folders"])
+            .unwrap();
+        assert_eq!("folders", command_path(&matches));
+    }
+
+    #[test]
+    fn test_no_input() {
+        let matches = create_cli()
+            .try_get_matches_from(["pcli2", "--no-input", "init"])
+            .unwrap();
+        assert!(no_input(&matches));
+    }
+}
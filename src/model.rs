@@ -1,26 +1,144 @@
 use crate::format::{
-    CsvRecordProducer, FormattingError, JsonProducer, OutputFormat, OutputFormatter,
+    format_item, to_ndjson, CsvRecordProducer, FormattingError, JsonProducer, OutputFormat,
+    OutputFormatter, YamlProducer,
 };
-use csv::Writer;
-use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, io::BufWriter};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum ModelError {
     #[error("missing property value {name:?}")]
     MissingPropertyValue { name: String },
+    #[error("failed to deserialize value at {pointer:?}: {message}")]
+    DeserializationFailure { pointer: String, message: String },
+    #[error("unexpected properties not present in the model: {pointers:?}")]
+    UnknownProperties { pointers: Vec<String> },
+}
+
+/// Converts a location reported by serde_ignored into a JSON pointer (RFC 6901)
+fn ignored_path_to_pointer(path: &serde_ignored::Path) -> String {
+    use serde_ignored::Path;
+
+    match path {
+        Path::Root => String::new(),
+        Path::Seq { parent, index } => format!("{}/{}", ignored_path_to_pointer(parent), index),
+        Path::Map { parent, key } => format!(
+            "{}/{}",
+            ignored_path_to_pointer(parent),
+            escape_pointer_token(key)
+        ),
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => ignored_path_to_pointer(parent),
+    }
+}
+
+/// Converts a location reported by serde_path_to_error into a JSON pointer (RFC 6901)
+fn error_path_to_pointer(path: &serde_path_to_error::Path) -> String {
+    use serde_path_to_error::Segment;
+
+    path.iter()
+        .map(|segment| match segment {
+            Segment::Seq { index } => format!("/{}", index),
+            Segment::Map { key } => format!("/{}", escape_pointer_token(key)),
+            Segment::Enum { variant } => format!("/{}", escape_pointer_token(variant)),
+            Segment::Unknown => String::from("/?"),
+        })
+        .collect()
+}
+
+fn escape_pointer_token(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+/// Type deserialized from the documents returned by the API with [`from_json`]
+///
+/// Types keeping the properties they do not model, e.g. in a flattened map, list them here, so that strict mode
+/// reports them just like the properties that other types ignore.
+pub trait Model: DeserializeOwned {
+    /// Returns JSON pointers to the properties that were kept without being modeled
+    fn unmodeled_properties(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// Returns JSON pointers to the keys of a map of unmodeled properties
+fn extra_pointers(extra: &Map<String, Value>) -> Vec<String> {
+    extra
+        .keys()
+        .map(|key| format!("/{}", escape_pointer_token(key)))
+        .collect()
+}
+
+/// Deserializes a JSON document returned by the API into a model type.
+///
+/// When the document does not match the model, the error identifies the offending value with a JSON pointer.
+/// In strict mode, properties that the model would otherwise silently ignore are reported as an error as well.
+pub fn from_json<T: Model>(json: &str, strict: bool) -> Result<T, ModelError> {
+    let mut unknown_properties: Vec<String> = Vec::new();
+    let mut deserializer = serde_json::Deserializer::from_str(json);
+
+    let result: Result<T, _> = {
+        let mut callback =
+            |path: serde_ignored::Path| unknown_properties.push(ignored_path_to_pointer(&path));
+        let deserializer = serde_ignored::Deserializer::new(&mut deserializer, &mut callback);
+        serde_path_to_error::deserialize(deserializer)
+    };
+
+    let value = match result {
+        Ok(value) => value,
+        Err(e) => {
+            return Err(ModelError::DeserializationFailure {
+                pointer: error_path_to_pointer(e.path()),
+                message: e.into_inner().to_string(),
+            })
+        }
+    };
+
+    if let Err(e) = deserializer.end() {
+        return Err(ModelError::DeserializationFailure {
+            pointer: String::new(),
+            message: e.to_string(),
+        });
+    }
+
+    if strict {
+        unknown_properties.extend(value.unmodeled_properties());
+    }
+    if strict && !unknown_properties.is_empty() {
+        return Err(ModelError::UnknownProperties {
+            pointers: unknown_properties,
+        });
+    }
+
+    Ok(value)
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Folder {
     id: u32,
     name: String,
+    /// Any properties returned by the API that are not explicitly modeled above.
+    /// They are preserved so that newly added server fields are not silently dropped.
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+impl Model for Folder {
+    fn unmodeled_properties(&self) -> Vec<String> {
+        extra_pointers(&self.extra)
+    }
 }
 
 impl Folder {
     pub fn new(id: u32, name: String) -> Folder {
-        Folder { id, name }
+        Folder {
+            id,
+            name,
+            extra: Map::new(),
+        }
     }
 
     #[allow(dead_code)]
@@ -41,6 +159,11 @@ impl Folder {
         self.name.clone()
     }
 
+    /// Returns the properties that were present in the API payload, but are not part of the model
+    pub fn extra(&self) -> &Map<String, Value> {
+        &self.extra
+    }
+
     pub fn builder() -> FolderBuilder {
         FolderBuilder::new()
     }
@@ -58,14 +181,13 @@ impl CsvRecordProducer for Folder {
 
 impl JsonProducer for Folder {}
 
+impl YamlProducer for Folder {}
+
 impl OutputFormatter for Folder {
     type Item = Folder;
 
     fn format(&self, format: OutputFormat) -> Result<String, FormattingError> {
-        match format {
-            OutputFormat::Json => Ok(self.to_json()?),
-            OutputFormat::Csv => Ok(self.to_csv_with_header()?),
-        }
+        format_item(self, format)
     }
 }
 
@@ -141,6 +263,11 @@ impl FolderList {
         self.folders.insert(folder.id, folder.clone());
     }
 
+    /// Keeps only the folders for which the predicate returns true
+    pub fn retain<F: Fn(&Folder) -> bool>(&mut self, predicate: F) {
+        self.folders.retain(|_, folder| predicate(folder));
+    }
+
     #[allow(dead_code)]
     pub fn remove(&mut self, id: &u32) {
         self.folders.remove(id);
@@ -196,26 +323,121 @@ impl OutputFormatter for FolderList {
                     Err(e) => Err(FormattingError::FormatFailure { cause: Box::new(e) }),
                 }
             }
-            OutputFormat::Csv => {
-                let buf = BufWriter::new(Vec::new());
-                let mut wtr = Writer::from_writer(buf);
-                wtr.write_record(&Self::csv_header()).unwrap();
-                for record in self.as_csv_records() {
-                    wtr.write_record(&record).unwrap();
-                }
-                match wtr.flush() {
-                    Ok(_) => {
-                        let bytes = wtr.into_inner().unwrap().into_inner().unwrap();
-                        let csv = String::from_utf8(bytes).unwrap();
-                        Ok(csv.clone())
-                    }
+            OutputFormat::Yaml => {
+                let yaml = serde_yaml::to_string(self);
+                match yaml {
+                    Ok(yaml) => Ok(yaml),
                     Err(e) => Err(FormattingError::FormatFailure { cause: Box::new(e) }),
                 }
             }
+            OutputFormat::Ndjson => {
+                let mut folders: Vec<&Folder> = self.folders.values().collect();
+                folders.sort_by_key(|folder| folder.id);
+                to_ndjson(folders)
+            }
+            OutputFormat::Table => Ok(self.to_table(false)?),
+            OutputFormat::Grid => Ok(self.to_table(true)?),
+            OutputFormat::Csv | OutputFormat::Template => Ok(self.to_csv_with_header()?),
         }
     }
 }
 
+/// The user the access token was issued to, as described by the claims of the token
+///
+/// Identity providers differ in the claims they put into access tokens, therefore all of them are optional.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    /// Subject of the token, the user ID or e-mail address depending on the identity provider
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sub: Option<String>,
+    #[serde(default, alias = "given_name", skip_serializing_if = "Option::is_none")]
+    first_name: Option<String>,
+    #[serde(
+        default,
+        alias = "family_name",
+        skip_serializing_if = "Option::is_none"
+    )]
+    last_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    roles: Option<Vec<String>>,
+    /// IDs of the tenants the user can access
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tenants: Option<Vec<String>>,
+    /// Any other claims of the token
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+impl Model for User {
+    fn unmodeled_properties(&self) -> Vec<String> {
+        extra_pointers(&self.extra)
+    }
+}
+
+impl User {
+    pub fn sub(&self) -> Option<String> {
+        self.sub.clone()
+    }
+
+    /// Returns the full name of the user, or an empty string if the token does not include one
+    pub fn name(&self) -> String {
+        [self.first_name.as_deref(), self.last_name.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+
+    pub fn email(&self) -> Option<String> {
+        self.email.clone()
+    }
+
+    pub fn roles(&self) -> Vec<String> {
+        self.roles.clone().unwrap_or_default()
+    }
+
+    pub fn tenants(&self) -> Vec<String> {
+        self.tenants.clone().unwrap_or_default()
+    }
+}
+
+impl CsvRecordProducer for User {
+    fn csv_header() -> Vec<String> {
+        vec![
+            "ID".to_string(),
+            "NAME".to_string(),
+            "EMAIL".to_string(),
+            "ROLES".to_string(),
+            "TENANTS".to_string(),
+        ]
+    }
+
+    fn as_csv_records(&self) -> Vec<Vec<String>> {
+        vec![vec![
+            self.sub().unwrap_or_default(),
+            self.name(),
+            self.email().unwrap_or_default(),
+            self.roles().join(" "),
+            self.tenants().join(" "),
+        ]]
+    }
+}
+
+impl JsonProducer for User {}
+
+impl YamlProducer for User {}
+
+impl OutputFormatter for User {
+    type Item = User;
+
+    fn format(&self, format: OutputFormat) -> Result<String, FormattingError> {
+        format_item(self, format)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -258,5 +480,147 @@ mod tests {
 120,folder_name
 "#;
         assert_eq!(csv_expected, csv);
+
+        let yaml = folder.format(OutputFormat::Yaml).unwrap();
+        let yaml_expected = r#"id: 120
+name: folder_name
+"#;
+        assert_eq!(yaml_expected, yaml);
+    }
+
+    #[test]
+    fn test_csv_escaping() {
+        let mut folders = FolderList::empty();
+        folders.insert(Folder::new(1, "Brackets, \"left\" side".to_string()));
+
+        let csv = folders.format(OutputFormat::Csv).unwrap();
+        assert_eq!("ID,NAME\n1,\"Brackets, \"\"left\"\" side\"\n", csv);
+
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        let record = reader.records().next().unwrap().unwrap();
+        assert_eq!("Brackets, \"left\" side", &record[1]);
+    }
+
+    #[test]
+    fn test_folder_list_ndjson() {
+        let mut folders = FolderList::empty();
+        folders.insert(Folder::new(2, "second".to_string()));
+        folders.insert(Folder::new(1, "first".to_string()));
+
+        let ndjson = folders.format(OutputFormat::Ndjson).unwrap();
+        assert_eq!(
+            "{\"id\":1,\"name\":\"first\"}\n{\"id\":2,\"name\":\"second\"}",
+            ndjson
+        );
+    }
+
+    #[test]
+    fn test_folder_preserves_unknown_fields() {
+        let json = r#"{"id":130,"name":"folder_name","ownerId":"abc","assetsCount":5}"#;
+
+        let folder: Folder = serde_json::from_str(json).unwrap();
+        assert_eq!(130, folder.id());
+        assert_eq!("folder_name", folder.name());
+        assert_eq!(2, folder.extra().len());
+        assert_eq!(Some(&Value::from("abc")), folder.extra().get("ownerId"));
+        assert_eq!(Some(&Value::from(5)), folder.extra().get("assetsCount"));
+
+        let round_trip: Value = serde_json::to_value(&folder).unwrap();
+        let original: Value = serde_json::from_str(json).unwrap();
+        assert_eq!(original, round_trip);
+    }
+
+    #[test]
+    fn test_user_output_format() {
+        let json = r#"{"sub":"00u1","given_name":"Jane","family_name":"Doe","email":"jane@example.com","roles":["admin","user"],"tenants":["tenant_a","tenant_b"],"cid":"abc"}"#;
+
+        let user: User = from_json(json, false).unwrap();
+        assert_eq!("Jane Doe", user.name());
+        assert_eq!(Some(&Value::from("abc")), user.extra.get("cid"));
+
+        let csv = user.format(OutputFormat::Csv).unwrap();
+        assert_eq!(
+            "ID,NAME,EMAIL,ROLES,TENANTS\n00u1,Jane Doe,jane@example.com,admin user,tenant_a tenant_b\n",
+            csv
+        );
+
+        // tokens commonly carry none of the user claims
+        let user: User = from_json(r#"{"exp":1700000000,"scp":["tenantApp"]}"#, false).unwrap();
+        assert_eq!("", user.name());
+        assert_eq!(None, user.email());
+        assert!(user.roles().is_empty());
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Sample {
+        id: u32,
+        children: Vec<Folder>,
+    }
+
+    impl Model for Sample {
+        fn unmodeled_properties(&self) -> Vec<String> {
+            self.children
+                .iter()
+                .enumerate()
+                .flat_map(|(index, child)| {
+                    child
+                        .unmodeled_properties()
+                        .into_iter()
+                        .map(move |pointer| format!("/children/{}{}", index, pointer))
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_from_json_reports_pointer_on_failure() {
+        let json = r#"{"id":1,"children":[{"id":2,"name":"a"},{"id":"three","name":"b"}]}"#;
+
+        match from_json::<Sample>(json, false) {
+            Err(ModelError::DeserializationFailure { pointer, .. }) => {
+                assert_eq!("/children/1/id", pointer)
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_from_json_strict_mode() {
+        let json = r#"{"id":1,"children":[],"a/b":true,"nested":{"x":1}}"#;
+
+        let sample = from_json::<Sample>(json, false).unwrap();
+        assert_eq!(1, sample.id);
+        assert!(sample.children.is_empty());
+
+        match from_json::<Sample>(json, true) {
+            Err(ModelError::UnknownProperties { pointers }) => {
+                assert_eq!(vec!["/a~1b".to_string(), "/nested".to_string()], pointers)
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_strict_mode_reports_unknown_folder_properties() {
+        let json = r#"{"id":130,"name":"folder_name","color":"red"}"#;
+
+        let folder = from_json::<Folder>(json, false).unwrap();
+        assert_eq!(Some(&Value::from("red")), folder.extra().get("color"));
+
+        match from_json::<Folder>(json, true) {
+            Err(ModelError::UnknownProperties { pointers }) => {
+                assert_eq!(vec!["/color".to_string()], pointers)
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let json =
+            r#"{"id":1,"children":[{"id":2,"name":"a"},{"id":3,"name":"b","ownerId":"abc"}]}"#;
+        match from_json::<Sample>(json, true) {
+            Err(ModelError::UnknownProperties { pointers }) => {
+                assert_eq!(vec!["/children/1/ownerId".to_string()], pointers)
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
     }
 }
@@ -1,23 +1,72 @@
-use super::configuration::TenantConfiguration;
+use super::configuration::{Configuration, TenantConfiguration};
 use crate::client::*;
+use crate::files::{write_private_atomically, FileLock};
+use crate::format::{
+    format_item, CsvRecordProducer, FormattingError, JsonProducer, OutputFormat, OutputFormatter,
+    YamlProducer,
+};
+use crate::model::{self, User};
+use aes_gcm::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Nonce,
+};
+use base64::{engine::general_purpose, Engine};
 use jsonwebtoken::decode_header;
 use keyring::Entry;
-use log::{error, trace};
+use log::{error, trace, warn};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::PathBuf};
 use thiserror::Error;
 
 pub const SECRET_KEY: &str = "secret";
 const TOKEN_KEY: &str = "token";
+const REFRESH_TOKEN_KEY: &str = "refresh_token";
+/// Poll interval of the device authorization flow when the provider does not specify one
+const DEVICE_POLL_INTERVAL: u64 = 5;
+
+/// Environment variable overriding the client ID of the tenant configuration
+pub const ENV_CLIENT_ID: &str = "PCLI2_CLIENT_ID";
+/// Environment variable providing the client secret instead of the keyring
+pub const ENV_CLIENT_SECRET: &str = "PCLI2_CLIENT_SECRET";
+/// Environment variable providing a ready access token instead of the keyring
+pub const ENV_ACCESS_TOKEN: &str = "PCLI2_ACCESS_TOKEN";
+/// Environment variable that can supply the passphrase instead of an interactive prompt or a key file
+pub const ENV_PASSPHRASE: &str = "PCLI2_PASSPHRASE";
+
+const PBKDF2_ROUNDS: u32 = 210_000;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
 
 #[derive(Debug, Error)]
 pub enum SecurityError {
     #[error("access denied")]
     AccessDenied,
+    #[error("the device code expired before the login was completed")]
+    DeviceCodeExpired,
     #[error("invalid credential")]
     InvalidCredentials,
-    #[error("keyring error")]
+    #[error("no token or client credentials are stored for this tenant, login first or set PCLI2_CLIENT_SECRET or PCLI2_ACCESS_TOKEN")]
+    TokenMissing,
+    #[error(
+        "the access token expired and there are no credentials to obtain a new one, login again"
+    )]
+    TokenExpired,
+    #[error("failed to refresh the access token, login again, because of: {0}")]
+    RefreshFailed(ClientError),
+    #[error("{0}")]
     KeyrinError(#[from] KeyringError),
     #[error("failed to decode token")]
     FailedToDecodeToken,
+    #[error("your token lacks scope {scope:?}")]
+    InsufficientScope { scope: String },
+    #[error("{0}")]
+    ClientError(#[from] ClientError),
+    #[error("failed to encrypt data")]
+    EncryptionFailure,
+    #[error("failed to decrypt data, the passphrase may be wrong")]
+    DecryptionFailure,
+    #[error("failed to lock the token for refreshing, because of: {0}")]
+    RefreshLockFailure(std::io::Error),
     #[error("securiy error")]
     SecurityError {
         #[from]
@@ -29,8 +78,51 @@ pub enum SecurityError {
 pub enum KeyringError {
     #[error("keyring error")]
     CannotAccessKeyringEntity(#[from] keyring::Error),
+    #[error("the keyring of the operating system is not available ({0}), unlock the keyring, switch to another backend with `pcli2 config set credential-store --type encrypted-file`, or configure a fallback store")]
+    KeyringUnavailable(keyring::Error),
+    #[error("failed to access the credential file, because of: {0}")]
+    CannotAccessCredentialFile(#[from] std::io::Error),
+    #[error(
+        "the credential file requires a passphrase, set PCLI2_PASSPHRASE or configure a key file"
+    )]
+    PassphraseRequired,
+    #[error("failed to decrypt the credential file, the passphrase may be wrong")]
+    CannotDecryptCredentials,
+    #[error("failed to encrypt the credential file")]
+    CannotEncryptCredentials,
+    #[error("the credential store only reads credentials from the environment, set PCLI2_CLIENT_SECRET or PCLI2_ACCESS_TOKEN instead")]
+    ReadOnly,
+}
+
+impl KeyringError {
+    /// Returns true if the keyring backend itself could not be used, e.g. it is locked or there is no secret service
+    pub fn is_unavailable(&self) -> bool {
+        matches!(self, KeyringError::KeyringUnavailable(_))
+    }
 }
 
+/// Backend keeping the client secrets and tokens of the tenants
+///
+/// The values are identified by the keyring ID of the tenant and a key, e.g. the client secret or the access token.
+pub trait CredentialStore {
+    /// Returns the value stored under the key, or None if there is none
+    fn get(&self, tenant: &str, key: String) -> Result<Option<String>, KeyringError>;
+
+    fn put(&self, tenant: &str, key: String, value: String) -> Result<(), KeyringError>;
+
+    fn delete(&self, tenant: &str, key: String) -> Result<(), KeyringError>;
+
+    /// Returns true if nothing can be stored, so that callers can skip writing instead of failing
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
+
+fn format_key(tenant: String, key: String) -> String {
+    [tenant, key].join(":").to_owned()
+}
+
+/// Credential store backed by the keyring (secret service) of the operating system
 pub struct Keyring {}
 
 impl Default for Keyring {
@@ -39,40 +131,498 @@ impl Default for Keyring {
     }
 }
 
-impl Keyring {
-    fn format_key(&self, tenant: String, key: String) -> String {
-        [tenant, key].join(":").to_owned()
+/// Tells the failures of the keyring backend apart from the failures of accessing a single entry
+fn keyring_error(error: keyring::Error) -> KeyringError {
+    match error {
+        keyring::Error::PlatformFailure(_) | keyring::Error::NoStorageAccess(_) => {
+            KeyringError::KeyringUnavailable(error)
+        }
+        _ => KeyringError::CannotAccessKeyringEntity(error),
     }
+}
 
-    pub fn get(&self, tenant: &String, key: String) -> Result<Option<String>, KeyringError> {
-        let key = self.format_key(tenant.to_owned(), key);
-        let entry = Entry::new("pcli2", key.as_str())?;
+impl CredentialStore for Keyring {
+    fn get(&self, tenant: &str, key: String) -> Result<Option<String>, KeyringError> {
+        let key = format_key(tenant.to_owned(), key);
+        let entry = Entry::new("pcli2", key.as_str()).map_err(keyring_error)?;
         match entry.get_password() {
             Ok(value) => Ok(Some(value)),
             Err(e) => match e {
                 keyring::Error::NoEntry => Ok(None),
-                _ => Err(KeyringError::from(e)),
+                _ => Err(keyring_error(e)),
             },
         }
     }
 
-    pub fn put(&self, tenant: &String, key: String, value: String) -> Result<(), KeyringError> {
-        let key = self.format_key(tenant.to_owned(), key);
-        let entry = Entry::new("pcli2", key.as_str())?;
-        entry.set_password(value.as_str())?;
+    fn put(&self, tenant: &str, key: String, value: String) -> Result<(), KeyringError> {
+        let key = format_key(tenant.to_owned(), key);
+        let entry = Entry::new("pcli2", key.as_str()).map_err(keyring_error)?;
+        entry.set_password(value.as_str()).map_err(keyring_error)?;
+        Ok(())
+    }
+
+    fn delete(&self, tenant: &str, key: String) -> Result<(), KeyringError> {
+        let key = format_key(tenant.to_owned(), key);
+        let entry = Entry::new("pcli2", key.as_str()).map_err(keyring_error)?;
+        entry.delete_password().map_err(keyring_error)?;
+        Ok(())
+    }
+}
+
+/// Credential store that switches to a fallback store when the primary one is not available
+///
+/// This lets the keyring be used on desktops while machines without a secret service fall back to
+/// an explicitly configured alternative.
+pub struct FallbackStore {
+    primary: Box<dyn CredentialStore>,
+    fallback: Box<dyn CredentialStore>,
+}
+
+impl FallbackStore {
+    pub fn new(
+        primary: Box<dyn CredentialStore>,
+        fallback: Box<dyn CredentialStore>,
+    ) -> FallbackStore {
+        FallbackStore { primary, fallback }
+    }
+
+    fn with_fallback<T>(
+        &self,
+        operation: impl Fn(&dyn CredentialStore) -> Result<T, KeyringError>,
+    ) -> Result<T, KeyringError> {
+        match operation(self.primary.as_ref()) {
+            Err(e) if e.is_unavailable() => {
+                warn!("{}, using the fallback credential store", e);
+                operation(self.fallback.as_ref())
+            }
+            result => result,
+        }
+    }
+}
+
+impl CredentialStore for FallbackStore {
+    fn get(&self, tenant: &str, key: String) -> Result<Option<String>, KeyringError> {
+        self.with_fallback(|store| store.get(tenant, key.clone()))
+    }
+
+    fn put(&self, tenant: &str, key: String, value: String) -> Result<(), KeyringError> {
+        self.with_fallback(|store| store.put(tenant, key.clone(), value.clone()))
+    }
+
+    fn delete(&self, tenant: &str, key: String) -> Result<(), KeyringError> {
+        self.with_fallback(|store| store.delete(tenant, key.clone()))
+    }
+}
+
+/// Credential store keeping all values in a single file encrypted with a passphrase
+///
+/// This is meant for servers without a secret service. The passphrase is read from the key file, if one
+/// is configured, or else from the environment variable `PCLI2_PASSPHRASE`.
+pub struct EncryptedFileStore {
+    path: PathBuf,
+    key_file: Option<PathBuf>,
+}
+
+impl EncryptedFileStore {
+    pub fn new(path: PathBuf, key_file: Option<PathBuf>) -> EncryptedFileStore {
+        EncryptedFileStore { path, key_file }
+    }
+
+    fn passphrase(&self) -> Result<String, KeyringError> {
+        match &self.key_file {
+            Some(key_file) => Ok(fs::read_to_string(key_file)?.trim().to_string()),
+            None => env_var(ENV_PASSPHRASE).ok_or(KeyringError::PassphraseRequired),
+        }
+    }
+
+    /// Reads all values from the file, which is treated as empty if it does not exist yet
+    fn load(&self) -> Result<HashMap<String, String>, KeyringError> {
+        if !self.path.exists() {
+            return Ok(HashMap::new());
+        }
+
+        let content = fs::read_to_string(&self.path)?;
+        let content = match decrypt_with_passphrase(&content, &self.passphrase()?) {
+            Ok(content) => content,
+            Err(_) => return Err(KeyringError::CannotDecryptCredentials),
+        };
+        match serde_json::from_str(&content) {
+            Ok(values) => Ok(values),
+            Err(_) => Err(KeyringError::CannotDecryptCredentials),
+        }
+    }
+
+    fn save(&self, values: &HashMap<String, String>) -> Result<(), KeyringError> {
+        let content = match serde_json::to_string(values) {
+            Ok(content) => content,
+            Err(_) => return Err(KeyringError::CannotEncryptCredentials),
+        };
+        let content = match encrypt_with_passphrase(&content, &self.passphrase()?) {
+            Ok(content) => content,
+            Err(_) => return Err(KeyringError::CannotEncryptCredentials),
+        };
+
+        write_private_atomically(&self.path, content.as_bytes())?;
+        Ok(())
+    }
+
+    /// Locks the file for a read-modify-save cycle, as all tenants share it
+    fn lock(&self) -> Result<FileLock, KeyringError> {
+        if let Some(directory) = self.path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        Ok(FileLock::acquire(&self.path)?)
+    }
+}
+
+impl CredentialStore for EncryptedFileStore {
+    fn get(&self, tenant: &str, key: String) -> Result<Option<String>, KeyringError> {
+        let key = format_key(tenant.to_owned(), key);
+        Ok(self.load()?.remove(&key))
+    }
+
+    fn put(&self, tenant: &str, key: String, value: String) -> Result<(), KeyringError> {
+        let key = format_key(tenant.to_owned(), key);
+        let _lock = self.lock()?;
+        let mut values = self.load()?;
+        values.insert(key, value);
+        self.save(&values)
+    }
+
+    fn delete(&self, tenant: &str, key: String) -> Result<(), KeyringError> {
+        let key = format_key(tenant.to_owned(), key);
+        let _lock = self.lock()?;
+        let mut values = self.load()?;
+        if values.remove(&key).is_some() {
+            self.save(&values)?;
+        }
         Ok(())
     }
+}
 
-    pub fn delete(&self, tenant: &String, key: String) -> Result<(), KeyringError> {
-        let key = self.format_key(tenant.to_owned(), key);
-        let entry = Entry::new("pcli2", key.as_str())?;
-        entry.delete_password()?;
+/// Credential store that keeps nothing, for when the credentials are only provided through the environment
+pub struct EnvironmentStore {}
+
+impl CredentialStore for EnvironmentStore {
+    /// Returns the client secret from the environment, other credentials are never stored
+    fn get(&self, _tenant: &str, key: String) -> Result<Option<String>, KeyringError> {
+        match key.as_str() {
+            SECRET_KEY => Ok(env_var(ENV_CLIENT_SECRET)),
+            _ => Ok(None),
+        }
+    }
+
+    fn put(&self, _tenant: &str, _key: String, _value: String) -> Result<(), KeyringError> {
+        Err(KeyringError::ReadOnly)
+    }
+
+    fn delete(&self, _tenant: &str, _key: String) -> Result<(), KeyringError> {
         Ok(())
     }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<sha2::Sha256>(passphrase.as_bytes(), salt, PBKDF2_ROUNDS, &mut key);
+    key
 }
 
+/// Encrypts a value with a key derived from the passphrase
+///
+/// The result is Base64 encoded and carries the random salt and nonce needed to decrypt it.
+pub fn encrypt_with_passphrase(value: &str, passphrase: &str) -> Result<String, SecurityError> {
+    let mut salt = [0u8; SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    let key = derive_key(passphrase, &salt);
+
+    let cipher = match Aes256Gcm::new_from_slice(&key) {
+        Ok(cipher) => cipher,
+        Err(_) => return Err(SecurityError::EncryptionFailure),
+    };
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = match cipher.encrypt(&nonce, value.as_bytes()) {
+        Ok(ciphertext) => ciphertext,
+        Err(_) => return Err(SecurityError::EncryptionFailure),
+    };
+
+    let mut data = Vec::with_capacity(SALT_LENGTH + NONCE_LENGTH + ciphertext.len());
+    data.extend_from_slice(&salt);
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(&ciphertext);
+    Ok(general_purpose::STANDARD.encode(data))
+}
+
+/// Decrypts a value produced by [`encrypt_with_passphrase`]
+pub fn decrypt_with_passphrase(value: &str, passphrase: &str) -> Result<String, SecurityError> {
+    let data = match general_purpose::STANDARD.decode(value.trim()) {
+        Ok(data) if data.len() > SALT_LENGTH + NONCE_LENGTH => data,
+        _ => return Err(SecurityError::DecryptionFailure),
+    };
+
+    let (salt, data) = data.split_at(SALT_LENGTH);
+    let (nonce, ciphertext) = data.split_at(NONCE_LENGTH);
+    let key = derive_key(passphrase, salt);
+
+    let cipher = match Aes256Gcm::new_from_slice(&key) {
+        Ok(cipher) => cipher,
+        Err(_) => return Err(SecurityError::DecryptionFailure),
+    };
+    match cipher.decrypt(Nonce::from_slice(nonce), ciphertext) {
+        Ok(plaintext) => match String::from_utf8(plaintext) {
+            Ok(plaintext) => Ok(plaintext),
+            Err(_) => Err(SecurityError::DecryptionFailure),
+        },
+        Err(_) => Err(SecurityError::DecryptionFailure),
+    }
+}
+
+/// The subset of the JWT access token claims that PCLI2 relies on
+///
+/// The claims are read without verifying the signature. They are only used to make decisions on the client side,
+/// while the server remains the authority on what the token allows.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenClaims {
+    /// Expiration time as seconds since the Unix epoch
+    pub exp: Option<u64>,
+    /// Granted scopes (Okta style)
+    pub scp: Option<Vec<String>>,
+    /// Granted scopes (RFC 8693 style, space separated)
+    pub scope: Option<String>,
+    /// Client ID the token was issued to
+    pub cid: Option<String>,
+    /// Subject
+    pub sub: Option<String>,
+}
+
+impl TokenClaims {
+    /// Decodes the claims from the payload of the token
+    pub fn from_token(token: &str) -> Result<TokenClaims, SecurityError> {
+        let payload = decode_payload(token)?;
+        match serde_json::from_slice::<TokenClaims>(&payload) {
+            Ok(claims) => Ok(claims),
+            Err(_) => Err(SecurityError::FailedToDecodeToken),
+        }
+    }
+
+    /// Returns true if the token lists its scopes, which not every identity provider does
+    pub fn has_scopes(&self) -> bool {
+        self.scp.is_some() || self.scope.is_some()
+    }
+
+    /// Returns all scopes granted to the token
+    pub fn scopes(&self) -> Vec<String> {
+        let mut scopes = self.scp.clone().unwrap_or_default();
+        if let Some(scope) = &self.scope {
+            for s in scope.split_whitespace() {
+                if !scopes.iter().any(|existing| existing == s) {
+                    scopes.push(s.to_string());
+                }
+            }
+        }
+        scopes
+    }
+
+    /// Fails with the first of the required scopes that was not granted to the token
+    pub fn require_scopes(&self, required: &[&str]) -> Result<(), SecurityError> {
+        let scopes = self.scopes();
+        match required
+            .iter()
+            .find(|scope| !scopes.iter().any(|granted| granted == *scope))
+        {
+            Some(scope) => Err(SecurityError::InsufficientScope {
+                scope: scope.to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns true if the token expires within the window (in seconds) from now
+    ///
+    /// Tokens without an expiration claim never need refreshing.
+    pub fn expires_within(&self, window: u64, now: u64) -> bool {
+        match self.exp {
+            Some(exp) => exp <= now.saturating_add(window),
+            None => false,
+        }
+    }
+}
+
+/// Returns the decoded payload of the token, without verifying its signature
+fn decode_payload(token: &str) -> Result<Vec<u8>, SecurityError> {
+    let payload = match token.split('.').nth(1) {
+        Some(payload) => payload,
+        None => return Err(SecurityError::FailedToDecodeToken),
+    };
+
+    match general_purpose::URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')) {
+        Ok(payload) => Ok(payload),
+        Err(_) => Err(SecurityError::FailedToDecodeToken),
+    }
+}
+
+/// Returns the current time as seconds since the Unix epoch
+pub fn unix_time_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Returns the value of the environment variable, unless it is unset or empty
+pub(crate) fn env_var(name: &str) -> Option<String> {
+    match std::env::var(name) {
+        Ok(value) if !value.is_empty() => Some(value),
+        _ => None,
+    }
+}
+
+/// Summary of the stored credentials of a tenant, as reported by `auth status`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuthStatus {
+    tenant: String,
+    tenant_id: String,
+    client_id: String,
+    has_token: bool,
+    expires_at: Option<String>,
+    expired: Option<bool>,
+    scopes: Vec<String>,
+    can_refresh: bool,
+}
+
+impl AuthStatus {
+    /// Describes the token of a tenant as of the given time (seconds since the Unix epoch)
+    ///
+    /// A token that cannot be decoded is reported as if there was none.
+    pub fn new(
+        tenant: &str,
+        tenant_config: &TenantConfiguration,
+        token: Option<&str>,
+        can_refresh: bool,
+        now: u64,
+    ) -> AuthStatus {
+        let claims = token.and_then(|token| TokenClaims::from_token(token).ok());
+
+        let (expires_at, expired) = match claims.as_ref().and_then(|claims| claims.exp) {
+            Some(exp) => {
+                let expires_at = chrono::DateTime::from_timestamp(exp as i64, 0)
+                    .map(|time| time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+                (expires_at, Some(exp <= now))
+            }
+            None => (None, None),
+        };
+
+        AuthStatus {
+            tenant: tenant.to_string(),
+            tenant_id: tenant_config.tenant_id(),
+            client_id: claims
+                .as_ref()
+                .and_then(|claims| claims.cid.clone())
+                .unwrap_or_else(|| tenant_config.client_id()),
+            has_token: claims.is_some(),
+            expires_at,
+            expired,
+            scopes: claims.map(|claims| claims.scopes()).unwrap_or_default(),
+            can_refresh,
+        }
+    }
+
+    pub fn has_token(&self) -> bool {
+        self.has_token
+    }
+
+    pub fn expired(&self) -> Option<bool> {
+        self.expired
+    }
+
+    pub fn scopes(&self) -> Vec<String> {
+        self.scopes.clone()
+    }
+}
+
+impl CsvRecordProducer for AuthStatus {
+    fn csv_header() -> Vec<String> {
+        vec![
+            String::from("TENANT"),
+            String::from("TENANT_ID"),
+            String::from("CLIENT_ID"),
+            String::from("HAS_TOKEN"),
+            String::from("EXPIRES_AT"),
+            String::from("EXPIRED"),
+            String::from("SCOPES"),
+            String::from("CAN_REFRESH"),
+        ]
+    }
+
+    fn as_csv_records(&self) -> Vec<Vec<String>> {
+        vec![vec![
+            self.tenant.clone(),
+            self.tenant_id.clone(),
+            self.client_id.clone(),
+            self.has_token.to_string(),
+            self.expires_at.clone().unwrap_or_default(),
+            self.expired.map(|e| e.to_string()).unwrap_or_default(),
+            self.scopes.join(" "),
+            self.can_refresh.to_string(),
+        ]]
+    }
+}
+
+impl JsonProducer for AuthStatus {}
+
+impl YamlProducer for AuthStatus {}
+
+impl OutputFormatter for AuthStatus {
+    type Item = AuthStatus;
+
+    fn format(&self, format: OutputFormat) -> Result<String, FormattingError> {
+        format_item(self, format)
+    }
+}
+
+/// Lock held while the token of a tenant is refreshed and stored
+///
+/// Processes running in parallel would otherwise all refresh an expiring token at the same time and overwrite
+/// each other's keyring entries. The lock file is kept next to the configuration file in use, and the lock is
+/// released when dropped or when the process exits.
+struct RefreshLock {
+    _lock: FileLock,
+}
+
+impl RefreshLock {
+    /// Waits until no other process is refreshing the token of the tenant
+    fn acquire(client: &PhysnaHttpClient) -> Result<RefreshLock, SecurityError> {
+        let mut path = match client.configuration_path() {
+            Some(path) => path,
+            None => Configuration::get_default_configuration_file_path()?,
+        };
+        path.set_file_name(client.tenant_configuration().keyring_id().replace('/', "_"));
+        if let Some(directory) = path.parent() {
+            // the configuration directory does not exist yet before the first save
+            fs::create_dir_all(directory).map_err(SecurityError::RefreshLockFailure)?;
+        }
+
+        trace!("Waiting for any other process refreshing the token...");
+        match FileLock::acquire(&path) {
+            Ok(lock) => Ok(RefreshLock { _lock: lock }),
+            Err(e) => Err(SecurityError::RefreshLockFailure(e)),
+        }
+    }
+}
+
+/// Scopes without which no API command can succeed
+pub const REQUIRED_SCOPES: [&str; 1] = [SCOPE_TENANT_APP];
+
+/// Authenticated session with a tenant
+///
+/// The session owns the HTTP client it was established with, so that subsequent API calls reuse
+/// both the token and the client connections.
 pub struct TenantSession {
     token: Option<String>,
+    client: PhysnaHttpClient,
 }
 
 impl TenantSession {
@@ -80,20 +630,96 @@ impl TenantSession {
         self.token.clone()
     }
 
-    fn get_token_from_keyring(tenant: &String) -> Result<Option<String>, SecurityError> {
-        match Keyring::default().get(tenant, String::from(TOKEN_KEY))? {
+    /// Returns the HTTP client of this session
+    pub fn client(&self) -> &PhysnaHttpClient {
+        &self.client
+    }
+
+    /// Returns the claims of the session token, if there is one
+    pub fn claims(&self) -> Result<Option<TokenClaims>, SecurityError> {
+        match &self.token {
+            Some(token) => Ok(Some(TokenClaims::from_token(token)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the user the session token was issued to, as described by its claims
+    pub fn user(&self) -> Result<User, SecurityError> {
+        let token = match &self.token {
+            Some(token) => token,
+            None => return Err(SecurityError::TokenMissing),
+        };
+        let payload = decode_payload(token)?;
+        match model::from_json::<User>(&String::from_utf8_lossy(&payload), false) {
+            Ok(user) => Ok(user),
+            Err(_) => Err(SecurityError::FailedToDecodeToken),
+        }
+    }
+
+    /// Fails early if the session token was not granted all of the required scopes
+    pub fn require_scopes(&self, required: &[&str]) -> Result<(), SecurityError> {
+        match self.claims()? {
+            Some(claims) => claims.require_scopes(required),
+            None => Err(SecurityError::TokenMissing),
+        }
+    }
+
+    /// Returns the token stored for the tenant, if any, without contacting the identity provider
+    ///
+    /// A token provided through the environment takes precedence over the keyring.
+    pub fn stored_token(
+        tenant_config: &TenantConfiguration,
+    ) -> Result<Option<String>, SecurityError> {
+        match env_var(ENV_ACCESS_TOKEN) {
+            Some(token) => Ok(Some(token)),
+            None => Self::get_token_from_keyring(tenant_config),
+        }
+    }
+
+    /// Returns true if client credentials are available to request a new token
+    pub fn has_client_secret(tenant_config: &TenantConfiguration) -> bool {
+        env_var(ENV_CLIENT_SECRET).is_some() || tenant_config.client_secret().is_ok()
+    }
+
+    /// Returns true if a new token can be obtained without user interaction
+    pub fn can_refresh(tenant_config: &TenantConfiguration) -> bool {
+        Self::has_client_secret(tenant_config)
+            || matches!(
+                tenant_config
+                    .credential_store()
+                    .get(&tenant_config.keyring_id(), String::from(REFRESH_TOKEN_KEY)),
+                Ok(Some(_))
+            )
+    }
+
+    fn get_token_from_keyring(
+        tenant_config: &TenantConfiguration,
+    ) -> Result<Option<String>, SecurityError> {
+        let store = tenant_config.credential_store();
+        match store.get(&tenant_config.keyring_id(), String::from(TOKEN_KEY))? {
             Some(token) => Ok(Some(token)),
             None => Ok(None),
         }
     }
 
-    pub fn save_token_to_keyring(tenant: &String, token: &String) -> Result<(), SecurityError> {
-        Keyring::default().put(tenant, String::from(TOKEN_KEY), token.to_owned())?;
+    pub fn save_token_to_keyring(
+        tenant_config: &TenantConfiguration,
+        token: &String,
+    ) -> Result<(), SecurityError> {
+        tenant_config.credential_store().put(
+            &tenant_config.keyring_id(),
+            String::from(TOKEN_KEY),
+            token.to_owned(),
+        )?;
         Ok(())
     }
 
-    pub fn delete_token_from_keystore(tenant: &String) -> Result<(), SecurityError> {
-        Keyring::default().delete(tenant, String::from(TOKEN_KEY))?;
+    pub fn delete_token_from_keystore(
+        tenant_config: &TenantConfiguration,
+    ) -> Result<(), SecurityError> {
+        tenant_config
+            .credential_store()
+            .delete(&tenant_config.keyring_id(), String::from(TOKEN_KEY))?;
         Ok(())
     }
 
@@ -104,56 +730,586 @@ impl TenantSession {
         }
     }
 
+    fn request_token(
+        client: PhysnaHttpClient,
+        client_secret: String,
+        persist: bool,
+    ) -> Result<TenantSession, SecurityError> {
+        let tenant_config = client.tenant_configuration();
+        let response =
+            client.request_new_token_from_provider(Self::client_id(&tenant_config), client_secret);
+        match response {
+            Ok(token) => {
+                Self::check_required_scopes(&token)?;
+                if persist {
+                    Self::save_token_to_keyring(&tenant_config, &token)?;
+                }
+                Ok(TenantSession {
+                    token: Some(token),
+                    client,
+                })
+            }
+            Err(e) => {
+                error!("Error: {}", e);
+                match e {
+                    ClientError::UnexpectedResponse(status)
+                        if status == reqwest::StatusCode::UNAUTHORIZED =>
+                    {
+                        Err(SecurityError::AccessDenied)
+                    }
+                    _ => Err(SecurityError::from(e)),
+                }
+            }
+        }
+    }
+
+    /// Returns the claims of the token, or no claims if its payload cannot be read
+    fn claims_of(token: &str) -> TokenClaims {
+        TokenClaims::from_token(token).unwrap_or_default()
+    }
+
+    /// Fails early if the token lacks the scopes required by the commands
+    ///
+    /// Tokens that do not list their scopes are let through, the API then decides what they allow.
+    fn check_required_scopes(token: &str) -> Result<(), SecurityError> {
+        let claims = Self::claims_of(token);
+        match claims.has_scopes() {
+            true => claims.require_scopes(&REQUIRED_SCOPES),
+            false => Ok(()),
+        }
+    }
+
+    fn client_id(tenant_config: &TenantConfiguration) -> String {
+        env_var(ENV_CLIENT_ID).unwrap_or_else(|| tenant_config.client_id())
+    }
+
+    /// Validates and stores the tokens of an interactive login
+    fn accept_grant(
+        client: PhysnaHttpClient,
+        grant: TokenGrant,
+    ) -> Result<TenantSession, SecurityError> {
+        let tenant_config = client.tenant_configuration();
+        Self::check_required_scopes(&grant.access_token)?;
+        Self::save_token_to_keyring(&tenant_config, &grant.access_token)?;
+        if let Some(refresh_token) = &grant.refresh_token {
+            tenant_config.credential_store().put(
+                &tenant_config.keyring_id(),
+                String::from(REFRESH_TOKEN_KEY),
+                refresh_token.to_owned(),
+            )?;
+        }
+        Ok(TenantSession {
+            token: Some(grant.access_token),
+            client,
+        })
+    }
+
     fn force_login(
         client: PhysnaHttpClient,
         tenant_config: TenantConfiguration,
     ) -> Result<TenantSession, SecurityError> {
         trace!("Logging in...");
-        match Keyring::default().get(&tenant_config.tenant_id(), String::from(SECRET_KEY))? {
-            Some(secret) => {
-                let response = client.request_new_token_from_provider(secret);
-                match response {
-                    Ok(token) => {
-                        Self::save_token_to_keyring(&tenant_config.tenant_id(), &token)?;
-                        Ok(TenantSession { token: Some(token) })
-                    }
-                    Err(e) => {
-                        error!("Error: {}", e);
-                        Err(SecurityError::AccessDenied)
+        let keyring = tenant_config.credential_store();
+        let keyring_id = tenant_config.keyring_id();
+
+        // a refresh token is only stored by an interactive login, which takes precedence
+        if let Some(refresh_token) = keyring.get(&keyring_id, String::from(REFRESH_TOKEN_KEY))? {
+            trace!("Refreshing the token of an interactive login...");
+            match client.refresh_token(Self::client_id(&tenant_config), &refresh_token) {
+                Ok(grant) => return Self::accept_grant(client, grant),
+                Err(e) => {
+                    error!("Error: {}", e);
+                    if keyring
+                        .get(&keyring_id, String::from(SECRET_KEY))?
+                        .is_none()
+                    {
+                        return Err(SecurityError::RefreshFailed(e));
                     }
                 }
             }
-            None => Err(SecurityError::InvalidCredentials),
         }
+
+        match keyring.get(&keyring_id, String::from(SECRET_KEY))? {
+            Some(secret) => Self::request_token(client, secret, true),
+            None => Err(SecurityError::TokenMissing),
+        }
+    }
+
+    /// Obtains a new token with the stored credentials and stores it, even if the current token is still valid
+    ///
+    pub fn refresh(client: PhysnaHttpClient) -> Result<TenantSession, SecurityError> {
+        let tenant_config = client.tenant_configuration();
+        trace!(
+            "Refreshing the token for tenant \"{}\"...",
+            tenant_config.tenant_id()
+        );
+        let _lock = RefreshLock::acquire(&client)?;
+        Self::force_login(client, tenant_config)
+    }
+
+    /// Obtains a new token while holding the refresh lock of the tenant
+    ///
+    /// If another process stored a fresh token while this one was waiting for the lock, that token is used instead.
+    fn locked_login(
+        client: PhysnaHttpClient,
+        tenant_config: TenantConfiguration,
+    ) -> Result<TenantSession, SecurityError> {
+        let _lock = RefreshLock::acquire(&client)?;
+        let token = Self::get_token_from_keyring(&tenant_config)?.and_then(|token| {
+            let token = Self::validate_token(&token).ok()?;
+            Self::check_required_scopes(&token).ok()?;
+            match Self::claims_of(&token)
+                .expires_within(client.token_refresh_window(), unix_time_now())
+            {
+                true => None,
+                false => Some(token),
+            }
+        });
+
+        match token {
+            Some(token) => {
+                trace!("Another process already refreshed the token.");
+                Ok(TenantSession {
+                    token: Some(token),
+                    client,
+                })
+            }
+            None => Self::force_login(client, tenant_config),
+        }
+    }
+
+    /// Logs in interactively with the OAuth device authorization flow
+    ///
+    /// The prompt is shown the verification page and user code, then the provider is polled
+    /// until the user completes the login in a browser or the device code expires.
+    pub fn device_login<F: Fn(&DeviceAuthorization)>(
+        client: PhysnaHttpClient,
+        prompt: F,
+    ) -> Result<TenantSession, SecurityError> {
+        let client_id = Self::client_id(&client.tenant_configuration());
+        let authorization = client.request_device_authorization(client_id.clone())?;
+        prompt(&authorization);
+
+        let mut interval = authorization.interval.unwrap_or(DEVICE_POLL_INTERVAL);
+        let deadline = unix_time_now().saturating_add(authorization.expires_in);
+        while unix_time_now() < deadline {
+            std::thread::sleep(std::time::Duration::from_secs(interval));
+            match client.poll_device_token(client_id.clone(), &authorization.device_code) {
+                Ok(DeviceTokenStatus::Pending) => trace!("Waiting for the user to log in..."),
+                Ok(DeviceTokenStatus::SlowDown) => interval += DEVICE_POLL_INTERVAL,
+                Ok(DeviceTokenStatus::Granted(grant)) => return Self::accept_grant(client, grant),
+                Err(ClientError::AuthorizationError(error)) if error == "access_denied" => {
+                    return Err(SecurityError::AccessDenied)
+                }
+                Err(ClientError::AuthorizationError(error)) if error == "expired_token" => {
+                    return Err(SecurityError::DeviceCodeExpired)
+                }
+                Err(e) => return Err(SecurityError::from(e)),
+            }
+        }
+
+        Err(SecurityError::DeviceCodeExpired)
     }
 
     /// Creates a new API session
     ///
     pub fn login(tenant_config: TenantConfiguration) -> Result<TenantSession, SecurityError> {
+        Self::login_with_client(PhysnaHttpClient::new(tenant_config)?)
+    }
+
+    /// Creates a new API session using an already configured HTTP client
+    ///
+    pub fn login_with_client(client: PhysnaHttpClient) -> Result<TenantSession, SecurityError> {
+        let tenant_config = client.tenant_configuration();
         let tenant = tenant_config.tenant_id();
         trace!("Attemting to login for tenant \"{}\"...", &tenant);
 
-        let client = PhysnaHttpClient::new(tenant_config.to_owned());
-        let token = Self::get_token_from_keyring(&tenant)?;
+        // Credentials from the environment (e.g. on CI runners) never touch the keyring
+        if let Some(token) = env_var(ENV_ACCESS_TOKEN) {
+            trace!("Using the access token from {}.", ENV_ACCESS_TOKEN);
+            let token = Self::validate_token(&token)?;
+            if Self::claims_of(&token).expires_within(0, unix_time_now()) {
+                return Err(SecurityError::TokenExpired);
+            }
+            Self::check_required_scopes(&token)?;
+            return Ok(TenantSession {
+                token: Some(token),
+                client,
+            });
+        }
+        if let Some(secret) = env_var(ENV_CLIENT_SECRET) {
+            trace!("Using the client secret from {}.", ENV_CLIENT_SECRET);
+            return Self::request_token(client, secret, false);
+        }
+
+        let token = Self::get_token_from_keyring(&tenant_config)?;
         match token {
             Some(token) => {
                 trace!("Found an existing token for this tenant. Validating...");
-                match Self::validate_token(&token) {
-                    Ok(token) => {
+                let token = Self::validate_token(&token).and_then(|token| {
+                    Self::check_required_scopes(&token)?;
+                    let claims = Self::claims_of(&token);
+                    Ok((token, claims))
+                });
+                match token {
+                    Ok((_, claims))
+                        if claims
+                            .expires_within(client.token_refresh_window(), unix_time_now()) =>
+                    {
+                        trace!("The existing token is about to expire. Refreshing...");
+                        let expired = claims.expires_within(0, unix_time_now());
+                        Self::locked_login(client, tenant_config).map_err(|e| match e {
+                            SecurityError::TokenMissing if expired => SecurityError::TokenExpired,
+                            e => e,
+                        })
+                    }
+                    Ok((token, _)) => {
                         trace!("The existing token is still valid.");
-                        Ok(TenantSession { token: Some(token) })
+                        Ok(TenantSession {
+                            token: Some(token),
+                            client,
+                        })
                     }
-                    Err(_) => Self::force_login(client, tenant_config),
+                    Err(_) => Self::locked_login(client, tenant_config),
                 }
             }
-            None => Self::force_login(client, tenant_config),
+            None => Self::locked_login(client, tenant_config),
         }
     }
 
     /// Invalidates the API session if one exists for this tenant
     ///
     pub fn logoff(tenant_config: TenantConfiguration) -> Result<(), SecurityError> {
-        Self::delete_token_from_keystore(&tenant_config.tenant_id())?;
+        let keyring = tenant_config.credential_store();
+        let keyring_id = tenant_config.keyring_id();
+        if keyring
+            .get(&keyring_id, String::from(REFRESH_TOKEN_KEY))?
+            .is_some()
+        {
+            keyring.delete(&keyring_id, String::from(REFRESH_TOKEN_KEY))?;
+        }
+        Self::delete_token_from_keystore(&tenant_config)?;
         Ok(())
     }
+
+    /// Removes the tokens stored for the tenant, and also its client secret if requested
+    ///
+    /// Unlike [`TenantSession::logoff`], credentials that are not stored are skipped. A read-only store
+    /// has nothing to remove, as its credentials come from the environment.
+    pub fn wipe(
+        tenant_config: &TenantConfiguration,
+        include_secret: bool,
+    ) -> Result<(), SecurityError> {
+        let keyring = tenant_config.credential_store();
+        if keyring.is_read_only() {
+            trace!("The credential store is read-only, nothing to remove.");
+            return Ok(());
+        }
+        let keyring_id = tenant_config.keyring_id();
+        let keys = match include_secret {
+            true => vec![TOKEN_KEY, REFRESH_TOKEN_KEY, SECRET_KEY],
+            false => vec![TOKEN_KEY, REFRESH_TOKEN_KEY],
+        };
+        for key in keys {
+            if keyring.get(&keyring_id, String::from(key))?.is_some() {
+                keyring.delete(&keyring_id, String::from(key))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token_with_payload(payload: &str) -> String {
+        let header = general_purpose::URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256","typ":"JWT"}"#);
+        let payload = general_purpose::URL_SAFE_NO_PAD.encode(payload);
+        format!("{}.{}.signature", header, payload)
+    }
+
+    #[test]
+    fn test_token_claims_scopes() {
+        let token = token_with_payload(
+            r#"{"exp":1700000000,"scp":["tenantApp"],"scope":"roles tenantApp","cid":"my_client_id"}"#,
+        );
+
+        let claims = TokenClaims::from_token(&token).unwrap();
+        assert_eq!(Some(1700000000), claims.exp);
+        assert_eq!(Some("my_client_id".to_string()), claims.cid);
+        assert_eq!(
+            vec!["tenantApp".to_string(), "roles".to_string()],
+            claims.scopes()
+        );
+        assert!(claims.require_scopes(&REQUIRED_SCOPES).is_ok());
+
+        match claims.require_scopes(&["tenantApp", "admin"]) {
+            Err(SecurityError::InsufficientScope { scope }) => assert_eq!("admin", scope),
+            _ => panic!("expected insufficient scope"),
+        }
+    }
+
+    #[test]
+    fn test_encryption_round_trip() {
+        let secret = "my super secret secret";
+
+        let encrypted = encrypt_with_passphrase(secret, "passphrase").unwrap();
+        assert_ne!(secret, encrypted);
+        assert_ne!(
+            encrypted,
+            encrypt_with_passphrase(secret, "passphrase").unwrap()
+        );
+
+        assert_eq!(
+            secret,
+            decrypt_with_passphrase(&encrypted, "passphrase").unwrap()
+        );
+        assert!(decrypt_with_passphrase(&encrypted, "wrong passphrase").is_err());
+        assert!(decrypt_with_passphrase("not encrypted", "passphrase").is_err());
+    }
+
+    #[test]
+    fn test_encrypted_file_store() {
+        let directory = tempfile::tempdir().unwrap();
+        let key_file = directory.path().join("key");
+        fs::write(&key_file, "passphrase\n").unwrap();
+        let path = directory.path().join("credentials.enc");
+        let store = EncryptedFileStore::new(path.clone(), Some(key_file.clone()));
+        let tenant = "my_tenant".to_string();
+
+        assert_eq!(None, store.get(&tenant, SECRET_KEY.to_string()).unwrap());
+        store
+            .put(&tenant, SECRET_KEY.to_string(), "my secret".to_string())
+            .unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("my secret"));
+        assert_eq!(
+            Some("my secret".to_string()),
+            store.get(&tenant, SECRET_KEY.to_string()).unwrap()
+        );
+
+        fs::write(&key_file, "wrong passphrase").unwrap();
+        assert!(matches!(
+            store.get(&tenant, SECRET_KEY.to_string()),
+            Err(KeyringError::CannotDecryptCredentials)
+        ));
+
+        fs::write(&key_file, "passphrase").unwrap();
+        store.delete(&tenant, SECRET_KEY.to_string()).unwrap();
+        assert_eq!(None, store.get(&tenant, SECRET_KEY.to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_encrypted_file_store_concurrent_updates() {
+        let directory = tempfile::tempdir().unwrap();
+        let key_file = directory.path().join("key");
+        fs::write(&key_file, "passphrase").unwrap();
+        let path = directory.path().join("credentials.enc");
+
+        let threads: Vec<_> = (0..4)
+            .map(|index| {
+                let store = EncryptedFileStore::new(path.clone(), Some(key_file.clone()));
+                std::thread::spawn(move || {
+                    store
+                        .put(
+                            &format!("tenant_{}", index),
+                            SECRET_KEY.to_string(),
+                            format!("secret {}", index),
+                        )
+                        .unwrap();
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let store = EncryptedFileStore::new(path.clone(), Some(key_file));
+        for index in 0..4 {
+            assert_eq!(
+                Some(format!("secret {}", index)),
+                store
+                    .get(&format!("tenant_{}", index), SECRET_KEY.to_string())
+                    .unwrap()
+            );
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(0o600, mode & 0o777);
+        }
+    }
+
+    struct UnavailableStore {}
+
+    impl CredentialStore for UnavailableStore {
+        fn get(&self, _tenant: &str, _key: String) -> Result<Option<String>, KeyringError> {
+            Err(keyring_error(keyring::Error::NoStorageAccess(
+                "locked".into(),
+            )))
+        }
+
+        fn put(&self, _tenant: &str, _key: String, _value: String) -> Result<(), KeyringError> {
+            Err(keyring_error(keyring::Error::PlatformFailure(
+                "no secret service".into(),
+            )))
+        }
+
+        fn delete(&self, _tenant: &str, _key: String) -> Result<(), KeyringError> {
+            Err(keyring_error(keyring::Error::NoEntry))
+        }
+    }
+
+    #[test]
+    fn test_fallback_store() {
+        let directory = tempfile::tempdir().unwrap();
+        let key_file = directory.path().join("key");
+        fs::write(&key_file, "passphrase").unwrap();
+        let path = directory.path().join("credentials.enc");
+        let store = FallbackStore::new(
+            Box::new(UnavailableStore {}),
+            Box::new(EncryptedFileStore::new(path.clone(), Some(key_file))),
+        );
+        let tenant = "my_tenant".to_string();
+
+        store
+            .put(&tenant, SECRET_KEY.to_string(), "my secret".to_string())
+            .unwrap();
+        assert!(path.exists());
+        assert_eq!(
+            Some("my secret".to_string()),
+            store.get(&tenant, SECRET_KEY.to_string()).unwrap()
+        );
+
+        // only an unavailable backend falls back, other failures are reported as they are
+        assert!(matches!(
+            store.delete(&tenant, SECRET_KEY.to_string()),
+            Err(KeyringError::CannotAccessKeyringEntity(_))
+        ));
+    }
+
+    #[test]
+    fn test_refresh_lock() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut tenant_config = TenantConfiguration::new(
+            "lock_test_tenant".to_string(),
+            url::Url::parse("https://my_tenant.physna.com/api/v2").unwrap(),
+            url::Url::parse("https://authentication.com").unwrap(),
+            "my_client_id".to_string(),
+        );
+        tenant_config.set_profile(Some("lock_test".to_string()));
+        let mut client = PhysnaHttpClient::new(tenant_config).unwrap();
+        client.set_configuration_path(Some(directory.path().join("config.yml")));
+
+        // the lock is taken next to the configuration file
+        let path = directory.path().join("lock_test_lock_test_tenant.lock");
+        let lock = RefreshLock::acquire(&client).unwrap();
+        let other = fs::File::open(&path).unwrap();
+        assert!(other.try_lock().is_err());
+        drop(lock);
+        assert!(other.try_lock().is_ok());
+        other.unlock().unwrap();
+
+        // the lock can be taken again once released
+        let lock = RefreshLock::acquire(&client).unwrap();
+        drop(lock);
+    }
+
+    #[test]
+    fn test_environment_store() {
+        let store = EnvironmentStore {};
+        let tenant = "my_tenant".to_string();
+
+        assert!(matches!(
+            store.put(&tenant, TOKEN_KEY.to_string(), "token".to_string()),
+            Err(KeyringError::ReadOnly)
+        ));
+        assert_eq!(None, store.get(&tenant, TOKEN_KEY.to_string()).unwrap());
+        assert!(store.delete(&tenant, TOKEN_KEY.to_string()).is_ok());
+        assert!(store.is_read_only());
+
+        // logging out with --wipe has nothing to remove
+        let mut tenant_config = TenantConfiguration::new(
+            tenant,
+            url::Url::parse("https://my_tenant.physna.com/api/v2").unwrap(),
+            url::Url::parse("https://authentication.com").unwrap(),
+            "my_client_id".to_string(),
+        );
+        tenant_config
+            .set_credential_store(crate::configuration::CredentialStoreConfiguration::Environment);
+        assert!(TenantSession::wipe(&tenant_config, true).is_ok());
+    }
+
+    #[test]
+    fn test_auth_status() {
+        let tenant_config = TenantConfiguration::new(
+            "my_tenant".to_string(),
+            url::Url::parse("https://my_tenant.physna.com/api/v2").unwrap(),
+            url::Url::parse("https://authentication.com").unwrap(),
+            "configured_client_id".to_string(),
+        );
+        let token = token_with_payload(
+            r#"{"exp":1700000000,"scp":["tenantApp","roles"],"cid":"my_client_id"}"#,
+        );
+
+        let status = AuthStatus::new("alias", &tenant_config, Some(&token), true, 1600000000);
+        assert!(status.has_token());
+        assert_eq!(Some(false), status.expired());
+        assert_eq!(
+            vec!["tenantApp".to_string(), "roles".to_string()],
+            status.scopes()
+        );
+        let csv = status.format(OutputFormat::Csv).unwrap();
+        assert_eq!(
+            "TENANT,TENANT_ID,CLIENT_ID,HAS_TOKEN,EXPIRES_AT,EXPIRED,SCOPES,CAN_REFRESH\n\
+             alias,my_tenant,my_client_id,true,2023-11-14T22:13:20Z,false,tenantApp roles,true\n",
+            csv
+        );
+
+        let status = AuthStatus::new("alias", &tenant_config, Some(&token), true, 1800000000);
+        assert_eq!(Some(true), status.expired());
+
+        let status = AuthStatus::new("alias", &tenant_config, None, false, 1800000000);
+        assert!(!status.has_token());
+        assert_eq!(None, status.expired());
+        assert!(status
+            .format(OutputFormat::Csv)
+            .unwrap()
+            .ends_with("alias,my_tenant,configured_client_id,false,,,,false\n"));
+    }
+
+    #[test]
+    fn test_token_without_scope_claim() {
+        let claims = TokenClaims::from_token(&token_with_payload(r#"{"exp":1000}"#)).unwrap();
+        assert!(!claims.has_scopes());
+
+        // the API decides what such tokens allow
+        let token = token_with_payload(r#"{"exp":1000,"sub":"me"}"#);
+        assert!(TenantSession::check_required_scopes(&token).is_ok());
+        assert!(TenantSession::check_required_scopes("header.not-json.signature").is_ok());
+
+        let token = token_with_payload(r#"{"scp":["roles"]}"#);
+        assert!(matches!(
+            TenantSession::check_required_scopes(&token),
+            Err(SecurityError::InsufficientScope { .. })
+        ));
+    }
+
+    #[test]
+    fn test_token_claims_invalid_token() {
+        assert!(TokenClaims::from_token("not a token").is_err());
+        assert!(TokenClaims::from_token("a.%%%.c").is_err());
+    }
+
+    #[test]
+    fn test_token_expires_within() {
+        let claims = TokenClaims::from_token(&token_with_payload(r#"{"exp":1000}"#)).unwrap();
+        assert!(!claims.expires_within(60, 900));
+        assert!(claims.expires_within(60, 940));
+        assert!(claims.expires_within(0, 1000));
+
+        let claims = TokenClaims::from_token(&token_with_payload("{}")).unwrap();
+        assert!(!claims.expires_within(60, u64::MAX));
+    }
 }